@@ -7,8 +7,8 @@ use embassy_rp::{
     peripherals::PIO0,
     pio::{InterruptHandler, Pio},
 };
-use embassy_time::Timer;
-use pio_speed_encoder::Encoder;
+use embassy_time::Duration;
+use pio_speed_encoder::sampler::Sampler;
 use pio_speed_encoder::substep_version::{PioEncoder, PioEncoderProgram};
 use {defmt_rtt as _, panic_probe as _};
 
@@ -26,12 +26,12 @@ async fn main(_spawner: Spawner) {
     } = Pio::new(pio, Irqs);
 
     let prg = PioEncoderProgram::new(&mut common);
-    let mut encoder = PioEncoder::<_, 0, 30>::new(&mut common, sm0, p.PIN_16, p.PIN_17, &prg);
+    let encoder = PioEncoder::<_, 0, 30>::new(&mut common, sm0, p.PIN_16, p.PIN_17, &prg)
+        .expect("The encoder should start reading in phase with its pins");
 
-    loop {
-        encoder.update();
-        Timer::after_millis(10).await;
-        info!("speed{}", encoder.speed());
-        info!("sub steps:{}", encoder.position());
-    }
+    let mut sampler = Sampler::new(encoder, Duration::from_millis(10))
+        .with_idle_timeout(Duration::from_millis(30));
+    sampler
+        .run(|encoder| info!("{}", encoder.snapshot()))
+        .await
 }
@@ -0,0 +1,365 @@
+use embassy_time::Instant;
+
+use crate::{Encoder, GearRatio, Speed, Step, SubStep, UpdateError, step::SUBSTEPS_PER_STEP};
+
+/// Adapters that change how an [`Encoder`] reports its readings.
+///
+/// Each adapter is an [`Encoder`] itself, so they can be stacked:
+/// ```rust
+/// # use pio_speed_encoder_logic::{Encoder, EncoderExt, FilterConfig, GearRatio, SubStep};
+/// # fn wrap(encoder: impl Encoder) -> impl Encoder {
+/// encoder
+///     .inverted()
+///     .scaled(GearRatio::new(3, 1))
+///     .offset(SubStep::new(100))
+///     .filtered(FilterConfig::new(2))
+/// # }
+/// ```
+pub trait EncoderExt: Encoder + Sized {
+    /// Count the other way, like [`CountDirection::Inverted`](crate::CountDirection::Inverted).
+    fn inverted(self) -> Inverted<Self> {
+        Inverted { inner: self }
+    }
+    /// Shift the reported position by `offset`.
+    fn offset(self, offset: SubStep) -> Offset<Self> {
+        Offset {
+            inner: self,
+            offset,
+        }
+    }
+    /// Report readings on the output side of a gearbox.
+    fn scaled(self, ratio: GearRatio) -> Scaled<Self> {
+        Scaled::new(self, ratio)
+    }
+    /// Smooth the reported speed.
+    fn filtered(self, config: FilterConfig) -> Filtered<Self> {
+        Filtered {
+            inner: self,
+            config,
+            average: None,
+        }
+    }
+}
+
+impl<E: Encoder> EncoderExt for E {}
+
+/// See [`EncoderExt::inverted`].
+pub struct Inverted<E: Encoder> {
+    inner: E,
+}
+
+impl<E: Encoder> Inverted<E> {
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<E: Encoder> Encoder for Inverted<E> {
+    fn update(&mut self) {
+        self.inner.update();
+    }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        self.inner.try_update()
+    }
+    fn speed(&self) -> Speed {
+        Speed::from_raw(self.inner.speed().raw().wrapping_neg())
+    }
+    fn position(&self) -> SubStep {
+        SubStep::new(0) - self.inner.position()
+    }
+    /// Steps are mirrored so they still line up with the negated sub-steps.
+    fn ticks(&self) -> Step {
+        self.inner.ticks().mirror()
+    }
+    fn error_count(&self) -> Option<u32> {
+        self.inner.error_count()
+    }
+    fn sample_instant(&self) -> Option<Instant> {
+        self.inner.sample_instant()
+    }
+}
+
+/// See [`EncoderExt::offset`].
+pub struct Offset<E: Encoder> {
+    inner: E,
+    offset: SubStep,
+}
+
+impl<E: Encoder> Offset<E> {
+    pub fn offset(&self) -> SubStep {
+        self.offset
+    }
+    pub fn set_offset(&mut self, offset: SubStep) {
+        self.offset = offset;
+    }
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<E: Encoder> Encoder for Offset<E> {
+    fn update(&mut self) {
+        self.inner.update();
+    }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        self.inner.try_update()
+    }
+    fn speed(&self) -> Speed {
+        self.inner.speed()
+    }
+    fn position(&self) -> SubStep {
+        self.inner.position() + self.offset
+    }
+    /// Shifted by the offset in whole steps, rounded towards negative infinity.
+    fn ticks(&self) -> Step {
+        let steps = self
+            .offset
+            .raw()
+            .div_euclid(SUBSTEPS_PER_STEP.cast_signed());
+        Step::new(self.inner.ticks().raw().wrapping_add(steps))
+    }
+    fn error_count(&self) -> Option<u32> {
+        self.inner.error_count()
+    }
+    fn sample_instant(&self) -> Option<Instant> {
+        self.inner.sample_instant()
+    }
+}
+
+/// See [`EncoderExt::scaled`].
+///
+/// Position and ticks are accumulated from the change at every update,
+/// so they stay exact when the ratio does not divide evenly and when the inner counters wrap.
+/// Like [`LinearAxis`](crate::LinearAxis), `update` must be called at least once per 2^31 sub-steps of travel.
+pub struct Scaled<E: Encoder> {
+    inner: E,
+    ratio: GearRatio,
+    position: i64,
+    ticks: i64,
+    prev_position: SubStep,
+    prev_ticks: Step,
+}
+
+impl<E: Encoder> Scaled<E> {
+    fn new(inner: E, ratio: GearRatio) -> Self {
+        Self {
+            position: inner.position().raw().into(),
+            ticks: inner.ticks().raw().into(),
+            prev_position: inner.position(),
+            prev_ticks: inner.ticks(),
+            inner,
+            ratio,
+        }
+    }
+    pub fn ratio(&self) -> GearRatio {
+        self.ratio
+    }
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+    fn track(&mut self) {
+        self.position += i64::from((self.inner.position() - self.prev_position).raw());
+        self.ticks += i64::from(self.inner.ticks().raw().wrapping_sub(self.prev_ticks.raw()));
+        self.prev_position = self.inner.position();
+        self.prev_ticks = self.inner.ticks();
+    }
+    /// Apply the ratio, rounding towards negative infinity.
+    fn scale(&self, value: i64) -> i64 {
+        let scaled = (i128::from(value) * i128::from(self.ratio.output_turns()))
+            .div_euclid(i128::from(self.ratio.encoder_turns()));
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "the result is truncated to the counter width anyway"
+        )]
+        {
+            scaled as i64
+        }
+    }
+}
+
+impl<E: Encoder> Encoder for Scaled<E> {
+    fn update(&mut self) {
+        self.inner.update();
+        self.track();
+    }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        let result = self.inner.try_update();
+        self.track();
+        result
+    }
+    fn speed(&self) -> Speed {
+        let speed = self.scale(self.inner.speed().raw().into());
+        Speed::from_raw(i32::try_from(speed).unwrap_or(if speed < 0 { i32::MIN } else { i32::MAX }))
+    }
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "wraps the same way the PIO counter does"
+    )]
+    fn position(&self) -> SubStep {
+        SubStep::new(self.scale(self.position) as i32)
+    }
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "wraps the same way the PIO counter does"
+    )]
+    fn ticks(&self) -> Step {
+        Step::new(self.scale(self.ticks) as i32)
+    }
+    fn error_count(&self) -> Option<u32> {
+        self.inner.error_count()
+    }
+    fn sample_instant(&self) -> Option<Instant> {
+        self.inner.sample_instant()
+    }
+}
+
+/// Settings for [`EncoderExt::filtered`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FilterConfig {
+    shift: u8,
+}
+
+impl FilterConfig {
+    /// Exponential moving average that moves `1/2^shift` of the way to the new speed each update.
+    ///
+    /// # Panics
+    /// If `shift` is 16 or more.
+    pub const fn new(shift: u8) -> Self {
+        assert!(shift < 16, "The filter would never settle");
+        Self { shift }
+    }
+    pub const fn shift(&self) -> u8 {
+        self.shift
+    }
+}
+
+/// See [`EncoderExt::filtered`].
+///
+/// Only the speed is filtered, position and ticks are passed through.
+pub struct Filtered<E: Encoder> {
+    inner: E,
+    config: FilterConfig,
+    /// Average speed scaled up by `2^shift` to keep the fractional part.
+    average: Option<i64>,
+}
+
+impl<E: Encoder> Filtered<E> {
+    pub fn config(&self) -> FilterConfig {
+        self.config
+    }
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+    fn track(&mut self) {
+        let speed = i64::from(self.inner.speed().raw());
+        let shift = self.config.shift;
+        self.average = Some(match self.average {
+            // Start from the first reading rather than ramping up from zero.
+            None => speed << shift,
+            Some(average) => average + speed - (average >> shift),
+        });
+    }
+}
+
+impl<E: Encoder> Encoder for Filtered<E> {
+    fn update(&mut self) {
+        self.inner.update();
+        self.track();
+    }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        self.inner.try_update()?;
+        self.track();
+        Ok(())
+    }
+    /// The unfiltered speed until the first update.
+    fn speed(&self) -> Speed {
+        match self.average {
+            #[expect(
+                clippy::cast_possible_truncation,
+                reason = "the average of i32 values fits in an i32"
+            )]
+            Some(average) => Speed::from_raw((average >> self.config.shift) as i32),
+            None => self.inner.speed(),
+        }
+    }
+    fn position(&self) -> SubStep {
+        self.inner.position()
+    }
+    fn ticks(&self) -> Step {
+        self.inner.ticks()
+    }
+    fn error_count(&self) -> Option<u32> {
+        self.inner.error_count()
+    }
+    fn sample_instant(&self) -> Option<Instant> {
+        self.inner.sample_instant()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EncoderExt, FilterConfig};
+    use crate::{Encoder, GearRatio, Speed, Step, SubStep, mock::ScriptedEncoder};
+
+    #[test]
+    fn inverted_and_offset() {
+        let speeds = [Speed::stopped(), Speed::from_raw(10)];
+        let mut encoder = ScriptedEncoder::new(&[0, 70])
+            .with_speeds(&speeds)
+            .with_error_counts(&[0, 1])
+            .inverted()
+            .offset(SubStep::new(-128));
+        assert_eq!(encoder.position(), SubStep::new(-128));
+        assert_eq!(encoder.ticks(), Step::new(-3));
+        encoder.update();
+        assert_eq!(encoder.position(), SubStep::new(-198));
+        assert_eq!(encoder.ticks(), Step::new(-4));
+        assert_eq!(encoder.speed(), Speed::from_raw(-10));
+        assert_eq!(encoder.error_count(), Some(1));
+    }
+
+    #[test]
+    fn scaled_is_exact_across_wraps() {
+        let speeds = [Speed::from_raw(300)];
+        let mut encoder = ScriptedEncoder::new(&[i32::MAX - 10, i32::MIN + 20])
+            .with_speeds(&speeds)
+            .scaled(GearRatio::new(3, 1));
+        let start = encoder.position();
+        encoder.update();
+        // Moved 31 encoder sub-steps.
+        assert_eq!((encoder.position() - start).raw(), 10);
+        assert_eq!(encoder.speed(), Speed::from_raw(100));
+    }
+
+    #[test]
+    fn filtered_speed() {
+        let speeds = [0, 100, 100, 100].map(Speed::from_raw);
+        let mut encoder = ScriptedEncoder::new(&[0])
+            .with_speeds(&speeds)
+            .filtered(FilterConfig::new(1));
+        encoder.update();
+        assert_eq!(encoder.speed(), Speed::from_raw(100));
+        let speeds = [0, 0, 100, 100].map(Speed::from_raw);
+        let mut encoder = ScriptedEncoder::new(&[0])
+            .with_speeds(&speeds)
+            .filtered(FilterConfig::new(1));
+        encoder.update();
+        encoder.update();
+        assert_eq!(encoder.speed(), Speed::from_raw(50));
+        encoder.update();
+        assert_eq!(encoder.speed(), Speed::from_raw(75));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::step::SUBSTEPS_PER_STEP;
+use core::f32::consts::TAU;
+
+/// Gearing between the encoder shaft and the axis being measured.
+///
+/// A ratio of `3:1` means the encoder turns three times for every turn of the output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GearRatio {
+    encoder_turns: u32,
+    output_turns: u32,
+}
+
+impl GearRatio {
+    /// # Panics
+    /// If either side of the ratio is zero.
+    pub const fn new(encoder_turns: u32, output_turns: u32) -> Self {
+        assert!(
+            encoder_turns != 0 && output_turns != 0,
+            "Gear ratios must be non zero"
+        );
+        Self {
+            encoder_turns,
+            output_turns,
+        }
+    }
+    /// The encoder is directly coupled to the output.
+    pub const fn direct() -> Self {
+        Self::new(1, 1)
+    }
+    /// Encoder turns per `output_turns()` turns of the output.
+    pub const fn encoder_turns(&self) -> u32 {
+        self.encoder_turns
+    }
+    /// Output turns per `encoder_turns()` turns of the encoder.
+    pub const fn output_turns(&self) -> u32 {
+        self.output_turns
+    }
+}
+
+impl Default for GearRatio {
+    fn default() -> Self {
+        Self::direct()
+    }
+}
+
+/// Describes how encoder counts map onto revolutions of a rotary axis.
+///
+/// Internally all angles are stored in units of `1/output_turns` of a sub-step, which keeps the
+/// conversion exact even when the gear ratio does not divide evenly into the counts per revolution.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RotaryConfig {
+    counts_per_revolution: u32,
+    gear_ratio: GearRatio,
+}
+
+impl RotaryConfig {
+    /// `counts_per_revolution` is the number of [`Step`](crate::Step)s (quadrature counts) in one
+    /// revolution of the encoder shaft.
+    ///
+    /// # Panics
+    /// If `counts_per_revolution` is zero.
+    pub const fn new(counts_per_revolution: u32) -> Self {
+        assert!(counts_per_revolution != 0, "An encoder must have counts");
+        Self {
+            counts_per_revolution,
+            gear_ratio: GearRatio::direct(),
+        }
+    }
+    #[must_use]
+    pub const fn with_gear_ratio(mut self, gear_ratio: GearRatio) -> Self {
+        self.gear_ratio = gear_ratio;
+        self
+    }
+    pub const fn counts_per_revolution(&self) -> u32 {
+        self.counts_per_revolution
+    }
+    pub const fn gear_ratio(&self) -> GearRatio {
+        self.gear_ratio
+    }
+
+    /// Length of one output revolution in scaled units.
+    fn period(&self) -> u64 {
+        u64::from(self.counts_per_revolution)
+            * u64::from(SUBSTEPS_PER_STEP)
+            * u64::from(self.gear_ratio.encoder_turns)
+    }
+    fn scale(&self, sub_steps: i64) -> i128 {
+        i128::from(sub_steps) * i128::from(self.gear_ratio.output_turns)
+    }
+
+    /// Convert an unwrapped sub-step position into an angle of the output.
+    ///
+    /// # Panics
+    /// If the number of revolutions does not fit in an `i64`,
+    /// this would require more sub-steps than an `i64` can hold.
+    pub fn angle(&self, sub_steps: i64) -> Angle {
+        let period = self.period();
+        let scaled = self.scale(sub_steps);
+        let revolutions = scaled.div_euclid(i128::from(period));
+        let offset = scaled.rem_euclid(i128::from(period));
+        Angle {
+            revolutions: i64::try_from(revolutions).expect("Bounded by sub_steps"),
+            offset: u64::try_from(offset).expect("rem_euclid is always positive and < period"),
+            period,
+        }
+    }
+}
+
+/// Fraction of a revolution expressed as `offset/period`.
+fn fraction(offset: i128, period: u64) -> f32 {
+    #[expect(
+        clippy::cast_precision_loss,
+        reason = "Angles are only reported as floats, the exact value is kept internally"
+    )]
+    {
+        offset as f32 / period as f32
+    }
+}
+
+/// Position of a rotary axis within a revolution, plus the number of whole revolutions made.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Angle {
+    revolutions: i64,
+    offset: u64,
+    period: u64,
+}
+
+impl Angle {
+    /// Number of whole revolutions made since the zero position.
+    /// Negative values mean the axis has turned clockwise past zero.
+    pub fn revolution_count(&self) -> i64 {
+        self.revolutions
+    }
+    /// Angle within the current revolution in the range [0, 1).
+    pub fn revolutions(&self) -> f32 {
+        fraction(self.offset.into(), self.period)
+    }
+    /// Angle within the current revolution in the range [0, 360).
+    pub fn degrees(&self) -> f32 {
+        self.revolutions() * 360.0
+    }
+    /// Angle within the current revolution in the range [0, 2π).
+    pub fn radians(&self) -> f32 {
+        self.revolutions() * TAU
+    }
+    /// Shortest rotation that takes `self` to `other`, ignoring whole revolutions.
+    ///
+    /// Both angles must come from the same [`RotaryConfig`].
+    pub fn shortest_distance(&self, other: &Angle) -> AngularDistance {
+        debug_assert_eq!(self.period, other.period, "Angles use different configs");
+        let period = i128::from(self.period);
+        let delta = (i128::from(other.offset) - i128::from(self.offset)).rem_euclid(period);
+        // Prefer the positive direction when both ways around are the same length.
+        let delta = if delta * 2 > period {
+            delta - period
+        } else {
+            delta
+        };
+        AngularDistance {
+            delta,
+            period: self.period,
+        }
+    }
+}
+
+/// A signed rotation of at most half a revolution.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AngularDistance {
+    delta: i128,
+    period: u64,
+}
+
+impl AngularDistance {
+    /// Distance in revolutions, within [-0.5, 0.5].
+    pub fn revolutions(&self) -> f32 {
+        fraction(self.delta, self.period)
+    }
+    pub fn degrees(&self) -> f32 {
+        self.revolutions() * 360.0
+    }
+    pub fn radians(&self) -> f32 {
+        self.revolutions() * TAU
+    }
+}
+
+#[cfg(test)]
+#[expect(
+    clippy::float_cmp,
+    reason = "All tested angles are exactly representable"
+)]
+mod tests {
+    use super::{GearRatio, RotaryConfig};
+
+    /// 100 counts per revolution = 6400 sub-steps per revolution.
+    const CONFIG: RotaryConfig = RotaryConfig::new(100);
+
+    #[test]
+    fn quarter_turns() {
+        assert_eq!(CONFIG.angle(0).degrees(), 0.0);
+        assert_eq!(CONFIG.angle(1600).degrees(), 90.0);
+        assert_eq!(CONFIG.angle(3200).revolutions(), 0.5);
+        assert_eq!(CONFIG.angle(4800).radians(), 1.5 * core::f32::consts::PI);
+    }
+
+    #[test]
+    fn wraps_each_revolution() {
+        let angle = CONFIG.angle(6400 * 3 + 1600);
+        assert_eq!(angle.revolution_count(), 3);
+        assert_eq!(angle.degrees(), 90.0);
+
+        let angle = CONFIG.angle(-1600);
+        assert_eq!(angle.revolution_count(), -1);
+        assert_eq!(angle.degrees(), 270.0);
+    }
+
+    #[test]
+    fn gear_ratio() {
+        // Encoder turns 3 times per output turn, so 6400*3 sub-steps per revolution.
+        let config = CONFIG.with_gear_ratio(GearRatio::new(3, 1));
+        assert_eq!(config.angle(6400).revolution_count(), 0);
+        assert_eq!(config.angle(19200).revolution_count(), 1);
+        assert_eq!(config.angle(4800).degrees(), 90.0);
+
+        // Ratios that do not divide evenly are still exact over many revolutions.
+        let config = CONFIG.with_gear_ratio(GearRatio::new(7, 3));
+        let one_output_turn_in_thirds = 6400 * 7;
+        let angle = config.angle(one_output_turn_in_thirds * 1000);
+        assert_eq!(angle.revolution_count(), 3000);
+        assert_eq!(angle.degrees(), 0.0);
+    }
+
+    #[test]
+    fn shortest_distance() {
+        let near_end = CONFIG.angle(6400 - 320);
+        let near_start = CONFIG.angle(6400 * 5 + 320);
+        assert_eq!(near_end.shortest_distance(&near_start).degrees(), 36.0);
+        assert_eq!(near_start.shortest_distance(&near_end).degrees(), -36.0);
+
+        let zero = CONFIG.angle(0);
+        let half = CONFIG.angle(3200);
+        assert_eq!(zero.shortest_distance(&half).revolutions(), 0.5);
+        assert_eq!(half.shortest_distance(&zero).revolutions(), 0.5);
+    }
+}
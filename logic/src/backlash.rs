@@ -0,0 +1,316 @@
+use crate::{Direction, Encoder, GearRatio, step::SUBSTEPS_PER_STEP};
+
+/// How the motor and load encoders are coupled.
+///
+/// All estimates are reported in load sub-steps (64 per load encoder count).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BacklashConfig {
+    motor_counts_per_revolution: u32,
+    load_counts_per_revolution: u32,
+    gear_ratio: GearRatio,
+    threshold: Option<i64>,
+}
+
+impl BacklashConfig {
+    /// `gear_ratio` is motor turns to load turns.
+    ///
+    /// # Panics
+    /// If either encoder has zero counts per revolution.
+    pub const fn new(
+        motor_counts_per_revolution: u32,
+        load_counts_per_revolution: u32,
+        gear_ratio: GearRatio,
+    ) -> Self {
+        assert!(
+            motor_counts_per_revolution != 0 && load_counts_per_revolution != 0,
+            "An encoder must have counts"
+        );
+        Self {
+            motor_counts_per_revolution,
+            load_counts_per_revolution,
+            gear_ratio,
+            threshold: None,
+        }
+    }
+    /// Flag the gearbox for maintenance once the backlash is wider than `load_sub_steps`.
+    #[must_use]
+    pub const fn with_threshold(mut self, load_sub_steps: i64) -> Self {
+        self.threshold = Some(load_sub_steps);
+        self
+    }
+    /// Convert an unwrapped motor sub-step position into load sub-steps (rounded towards negative infinity).
+    pub fn motor_to_load(&self, motor_sub_steps: i64) -> i64 {
+        let numerator = i128::from(self.load_counts_per_revolution)
+            * i128::from(self.gear_ratio.output_turns());
+        let denominator = i128::from(self.motor_counts_per_revolution)
+            * i128::from(self.gear_ratio.encoder_turns());
+        let load = (i128::from(motor_sub_steps) * numerator).div_euclid(denominator);
+        i64::try_from(load).unwrap_or(if load < 0 { i64::MIN } else { i64::MAX })
+    }
+    /// Convert a number of load encoder counts into load sub-steps, handy for thresholds.
+    pub const fn load_counts(counts: i64) -> i64 {
+        counts * SUBSTEPS_PER_STEP as i64
+    }
+}
+
+/// Whether the gear teeth are in contact.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Engagement {
+    /// Neither encoder has moved yet.
+    Unknown,
+    /// The motor is driving the load in the given direction.
+    Engaged(Direction),
+    /// The motor reversed and is crossing the gap, heading in the given direction.
+    InGap(Direction),
+}
+
+/// Estimates backlash, lost motion and wind-up between a motor encoder and a load encoder.
+///
+/// The difference between the load position and the motor position (scaled through the gear ratio)
+/// is tracked every update. Both positions are interpolated from the encoders' transition timings,
+/// so the estimates have sub-step resolution.
+/// - When the motor reverses, the load stays put until the gap is crossed,
+///   the distance the motor travels in that time is the backlash.
+/// - While the teeth are in contact any change in the difference is torsional wind-up.
+///
+/// Like [`LinearAxis`](crate::LinearAxis), `update` must be called at least once per 2^31 sub-steps of travel.
+pub struct BacklashEstimator<M: Encoder, L: Encoder> {
+    motor: M,
+    load: L,
+    config: BacklashConfig,
+    motor_position: i64,
+    load_position: i64,
+    engagement: Engagement,
+    /// Difference when the teeth last made contact, or when the motor left the contact.
+    reference: i64,
+    /// Flank the motor left when entering the gap.
+    left_flank: Option<Direction>,
+    backlash: Option<i64>,
+}
+
+impl<M: Encoder, L: Encoder> BacklashEstimator<M, L> {
+    pub fn new(motor: M, load: L, config: BacklashConfig) -> Self {
+        Self {
+            motor,
+            load,
+            config,
+            motor_position: 0,
+            load_position: 0,
+            engagement: Engagement::Unknown,
+            reference: 0,
+            left_flank: None,
+            backlash: None,
+        }
+    }
+    pub fn config(&self) -> &BacklashConfig {
+        &self.config
+    }
+    pub fn motor(&self) -> &M {
+        &self.motor
+    }
+    pub fn load(&self) -> &L {
+        &self.load
+    }
+    /// Release the wrapped encoders.
+    pub fn into_inner(self) -> (M, L) {
+        (self.motor, self.load)
+    }
+
+    /// Load position minus motor position, in load sub-steps.
+    fn difference(&self) -> i64 {
+        self.load_position - self.config.motor_to_load(self.motor_position)
+    }
+
+    /// Update both encoders and the estimates.
+    pub fn update(&mut self) {
+        let prev_difference = self.difference();
+        let prev_motor = self.config.motor_to_load(self.motor_position);
+
+        let prev_position = self.motor.position();
+        self.motor.update();
+        self.motor_position += i64::from((self.motor.position() - prev_position).raw());
+        let prev_position = self.load.position();
+        self.load.update();
+        let load_delta = i64::from((self.load.position() - prev_position).raw());
+        self.load_position += load_delta;
+
+        let motor_direction =
+            direction(self.config.motor_to_load(self.motor_position) - prev_motor);
+        let load_direction = direction(load_delta);
+        let difference = self.difference();
+
+        self.engagement = match (self.engagement, motor_direction) {
+            (_, None) => self.engagement,
+            (Engagement::Unknown, Some(motor)) => {
+                if load_direction == Some(motor) {
+                    self.engage(motor, difference)
+                } else {
+                    Engagement::Unknown
+                }
+            }
+            (Engagement::Engaged(driving), Some(motor)) => {
+                if motor == driving {
+                    Engagement::Engaged(driving)
+                } else {
+                    // The motor left the flank at the end of the last update.
+                    self.left_flank = Some(driving);
+                    self.reference = prev_difference;
+                    self.gap_or_engage(motor, load_direction, difference)
+                }
+            }
+            (Engagement::InGap(_), Some(motor)) => {
+                self.gap_or_engage(motor, load_direction, difference)
+            }
+        };
+    }
+
+    fn gap_or_engage(
+        &mut self,
+        motor: Direction,
+        load: Option<Direction>,
+        difference: i64,
+    ) -> Engagement {
+        if load == Some(motor) {
+            if self.left_flank == Some(motor.invert()) {
+                // Crossed to the opposite flank.
+                self.backlash = Some((difference - self.reference).abs());
+            }
+            self.engage(motor, difference)
+        } else {
+            Engagement::InGap(motor)
+        }
+    }
+
+    fn engage(&mut self, direction: Direction, difference: i64) -> Engagement {
+        self.reference = difference;
+        self.left_flank = None;
+        Engagement::Engaged(direction)
+    }
+
+    pub fn engagement(&self) -> Engagement {
+        self.engagement
+    }
+    /// Width of the gap measured on the last full reversal, in load sub-steps.
+    pub fn backlash(&self) -> Option<i64> {
+        self.backlash
+    }
+    /// How far the motor has traveled into the gap since it reversed, zero while engaged.
+    pub fn lost_motion(&self) -> i64 {
+        match self.engagement {
+            Engagement::InGap(_) => (self.difference() - self.reference).abs(),
+            Engagement::Unknown | Engagement::Engaged(_) => 0,
+        }
+    }
+    /// Twist between the motor and the load since the teeth made contact, zero while in the gap.
+    ///
+    /// Positive values mean the load leads the motor.
+    pub fn wind_up(&self) -> i64 {
+        match self.engagement {
+            Engagement::Engaged(_) => self.difference() - self.reference,
+            Engagement::Unknown | Engagement::InGap(_) => 0,
+        }
+    }
+    /// True once the measured backlash is wider than the configured threshold.
+    pub fn needs_maintenance(&self) -> bool {
+        match (self.backlash, self.config.threshold) {
+            (Some(backlash), Some(threshold)) => backlash > threshold,
+            _ => false,
+        }
+    }
+}
+
+fn direction(delta: i64) -> Option<Direction> {
+    match delta.signum() {
+        1 => Some(Direction::CounterClockwise),
+        -1 => Some(Direction::Clockwise),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BacklashConfig, BacklashEstimator, Engagement};
+    use crate::{Direction, GearRatio, mock::ScriptedEncoder};
+
+    const DIRECT: BacklashConfig = BacklashConfig::new(100, 100, GearRatio::direct());
+
+    #[test]
+    fn gear_ratio() {
+        let config = BacklashConfig::new(100, 400, GearRatio::new(3, 1));
+        // 3 motor turns = 1 load turn.
+        assert_eq!(config.motor_to_load(3 * 6400), 25_600);
+        assert_eq!(config.motor_to_load(-3 * 6400), -25_600);
+    }
+
+    #[test]
+    fn measures_backlash_on_reversal() {
+        let motor = ScriptedEncoder::new(&[0, 320, 640, 600, 560, 512, 400, 300]);
+        let load = ScriptedEncoder::new(&[0, 320, 640, 640, 640, 640, 528, 428]);
+        let mut estimator = BacklashEstimator::new(
+            motor,
+            load,
+            DIRECT.with_threshold(BacklashConfig::load_counts(1)),
+        );
+        estimator.update();
+        estimator.update();
+        assert_eq!(
+            estimator.engagement(),
+            Engagement::Engaged(Direction::CounterClockwise)
+        );
+        assert_eq!(estimator.backlash(), None);
+
+        estimator.update();
+        assert_eq!(
+            estimator.engagement(),
+            Engagement::InGap(Direction::Clockwise)
+        );
+        assert_eq!(estimator.lost_motion(), 40);
+        estimator.update();
+        estimator.update();
+        assert_eq!(estimator.lost_motion(), 128);
+        assert!(!estimator.needs_maintenance());
+
+        estimator.update();
+        assert_eq!(
+            estimator.engagement(),
+            Engagement::Engaged(Direction::Clockwise)
+        );
+        assert_eq!(estimator.backlash(), Some(128));
+        assert_eq!(estimator.lost_motion(), 0);
+        assert!(estimator.needs_maintenance());
+
+        estimator.update();
+        assert_eq!(estimator.wind_up(), 0);
+    }
+
+    #[test]
+    fn returning_to_the_same_flank_is_not_backlash() {
+        let motor = ScriptedEncoder::new(&[0, 100, 50, 120]);
+        let load = ScriptedEncoder::new(&[0, 100, 100, 170]);
+        let mut estimator = BacklashEstimator::new(motor, load, DIRECT);
+        estimator.update();
+        estimator.update();
+        assert_eq!(estimator.lost_motion(), 50);
+        estimator.update();
+        assert_eq!(
+            estimator.engagement(),
+            Engagement::Engaged(Direction::CounterClockwise)
+        );
+        assert_eq!(estimator.backlash(), None);
+    }
+
+    #[test]
+    fn wind_up_while_engaged() {
+        let motor = ScriptedEncoder::new(&[0, 100, 300, 500]);
+        let load = ScriptedEncoder::new(&[0, 100, 260, 490]);
+        let mut estimator = BacklashEstimator::new(motor, load, DIRECT);
+        estimator.update();
+        estimator.update();
+        // The load lags behind the motor under torque.
+        assert_eq!(estimator.wind_up(), -40);
+        estimator.update();
+        assert_eq!(estimator.wind_up(), -10);
+    }
+}
@@ -0,0 +1,419 @@
+//! Models the position compare PIO program so its behavior can be tested on the host.
+//!
+//! The PIO program keeps the current target in X and compares it against the step count in Y
+//! every time Y changes. On a match it fires (pulses the output pin and raises an IRQ),
+//! then loads the next target from the TX FIFO.
+//! If the FIFO is empty the current target stays armed.
+use crate::Step;
+
+/// Depth of the TX FIFO when it is not joined with the RX FIFO.
+pub const TARGET_QUEUE_DEPTH: usize = 4;
+
+/// Generates evenly spaced targets, e.g. trigger a camera every 100 steps.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PitchTargets {
+    next: Step,
+    pitch: i32,
+}
+
+impl PitchTargets {
+    /// Targets at `first`, `first + pitch`, `first + 2*pitch`, ...
+    ///
+    /// A negative pitch generates targets for travel in the clockwise (decreasing) direction.
+    ///
+    /// # Panics
+    /// If `pitch` is zero.
+    pub fn new(first: Step, pitch: i32) -> Self {
+        assert_ne!(
+            pitch, 0,
+            "A pitch of zero would fire on the same step forever"
+        );
+        Self { next: first, pitch }
+    }
+}
+
+impl Iterator for PitchTargets {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let target = self.next;
+        self.next = Step::new(target.raw().wrapping_add(self.pitch));
+        Some(target)
+    }
+}
+
+/// Software model of the compare state machine.
+#[derive(Clone, Debug)]
+pub struct CompareModel {
+    /// The X register
+    target: Step,
+    /// The TX FIFO
+    queue: [Step; TARGET_QUEUE_DEPTH],
+    queued: usize,
+    /// The Y register
+    count: Step,
+}
+
+impl CompareModel {
+    pub fn new(count: Step, target: Step) -> Self {
+        Self {
+            target,
+            queue: [Step::new(0); TARGET_QUEUE_DEPTH],
+            queued: 0,
+            count,
+        }
+    }
+    /// The currently armed target.
+    pub fn target(&self) -> Step {
+        self.target
+    }
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+    /// Equivalent to pushing into the TX FIFO, returns false if the queue is full.
+    pub fn push(&mut self, target: Step) -> bool {
+        if self.queued == TARGET_QUEUE_DEPTH {
+            false
+        } else {
+            self.queue[self.queued] = target;
+            self.queued += 1;
+            true
+        }
+    }
+    /// Move the encoder to `count`, returns true if the output fired.
+    ///
+    /// Like the PIO program the count may only change by one step at a time.
+    ///
+    /// # Panics
+    /// If `count` is more than one step away from the current count.
+    pub fn step_to(&mut self, count: Step) -> bool {
+        let delta = count.raw().wrapping_sub(self.count.raw());
+        assert!(
+            delta.abs() <= 1,
+            "The PIO program only sees one step at a time"
+        );
+        if delta == 0 {
+            // The comparison only runs when the count changes.
+            return false;
+        }
+        self.count = count;
+        if self.count != self.target {
+            return false;
+        }
+        // PULL noblock, an empty FIFO leaves X unchanged.
+        if self.queued > 0 {
+            self.target = self.queue[0];
+            self.queue.copy_within(1..self.queued, 0);
+            self.queued -= 1;
+        }
+        true
+    }
+}
+
+/// Address of `MOV ISR, Y` in `quadrature_encoder_compare.pio`, the start of the main loop.
+pub const UPDATE: u8 = 15;
+/// Address of `PUSH noblock`, the first instruction after `ISR` stops holding the jump index.
+pub const AFTER_UPDATE: u8 = 16;
+/// Address of `MOV PC, ISR`.
+pub const DISPATCH: u8 = 20;
+/// Addresses of the fire path, from `IRQ` up to `MOV OSR, ISR side 0`.
+pub const FIRE: core::ops::RangeInclusive<u8> = 27..=30;
+
+/// An instruction the driver runs on the stopped state machine while loading a new target.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadInstruction {
+    /// `PUSH noblock`
+    Push,
+    /// `OUT ISR, 2`
+    OutIsr,
+    /// `IN PINS, 2`
+    InPins,
+    /// `MOV OSR, ISR`
+    MovOsrIsr,
+    /// `MOV OSR, ISR side 0`, also ends an output pulse.
+    EndPulse,
+    /// `PULL noblock`
+    Pull,
+    /// `MOV X, OSR`
+    MovXOsr,
+    /// `JMP` to an address.
+    Jmp(u8),
+}
+
+/// The sample instructions from [`AFTER_UPDATE`] up to the dispatch.
+const FINISH_SAMPLE: [LoadInstruction; 5] = [
+    LoadInstruction::Push,
+    LoadInstruction::OutIsr,
+    LoadInstruction::InPins,
+    LoadInstruction::MovOsrIsr,
+    LoadInstruction::Jmp(DISPATCH),
+];
+
+/// Abandon a match in progress and continue from the main loop.
+const ABANDON_FIRE: [LoadInstruction; 2] =
+    [LoadInstruction::EndPulse, LoadInstruction::Jmp(UPDATE)];
+
+/// Instructions that bring the state machine, stopped at `pc`, to a point where
+/// `ISR` holds the jump index and X is not about to be overwritten.
+///
+/// Between `PUSH` and `MOV PC, ISR` the sample is finished by hand.
+/// On the fire path the pending `MOV X, OSR` would overwrite the new target,
+/// so the pulse is ended and the state machine restarts the loop instead.
+pub fn park(pc: u8) -> &'static [LoadInstruction] {
+    if (AFTER_UPDATE..DISPATCH).contains(&pc) {
+        &FINISH_SAMPLE[usize::from(pc - AFTER_UPDATE)..]
+    } else if FIRE.contains(&pc) {
+        &ABANDON_FIRE
+    } else {
+        &[]
+    }
+}
+
+/// Run after [`park`] with the new target pushed into the empty TX FIFO.
+pub const LOAD: [LoadInstruction; 3] = [
+    LoadInstruction::Pull,
+    LoadInstruction::MovXOsr,
+    LoadInstruction::MovOsrIsr,
+];
+
+/// Register level model of `quadrature_encoder_compare.pio`, to check the driver's
+/// target loading against every point the state machine can be stopped at.
+///
+/// Delays are ignored, one call to [`Self::step`] runs one instruction.
+#[derive(Clone, Debug)]
+pub struct CompareMachine {
+    pub pc: u8,
+    pub x: u32,
+    pub y: u32,
+    pub isr: u32,
+    pub osr: u32,
+    /// Single entry TX FIFO.
+    pub tx: Option<u32>,
+    /// Input pins as `B << 1 | A`.
+    pub pins: u32,
+    pub output: bool,
+    pub irq: bool,
+}
+
+/// Jump table of the compare program, indexed by `old state << 2 | new state`.
+const TABLE: [u8; 15] = [
+    UPDATE, 25, 21, UPDATE, 21, UPDATE, UPDATE, 25, 25, UPDATE, UPDATE, 21, UPDATE, 21, 25,
+];
+
+impl CompareMachine {
+    /// Count zero with the pins low and `target` armed, starting at the main loop.
+    pub fn new(target: Step) -> Self {
+        Self {
+            pc: UPDATE,
+            x: target.raw().cast_unsigned(),
+            y: 0,
+            isr: 0,
+            osr: 0,
+            tx: None,
+            pins: 0,
+            output: false,
+            irq: false,
+        }
+    }
+    pub fn count(&self) -> Step {
+        Step::new(self.y.cast_signed())
+    }
+    /// Run the instruction at the program counter.
+    pub fn step(&mut self) {
+        let pc = self.pc;
+        self.pc = pc + 1;
+        match pc {
+            0..=14 => self.pc = TABLE[usize::from(pc)],
+            // The read 11 entry of the 11 state.
+            15 => self.isr = self.y,
+            16 => self.isr = 0,
+            21 | 23 => self.y = !self.y,
+            22 | 25 => self.y = self.y.wrapping_sub(1),
+            24 => self.pc = 26,
+            26 => {
+                if self.x != self.y {
+                    self.pc = UPDATE;
+                }
+            }
+            27 => {
+                self.irq = true;
+                self.output = true;
+            }
+            30 => {
+                self.exec(LoadInstruction::EndPulse);
+                self.pc = UPDATE;
+            }
+            _ => self.exec(match pc {
+                17 => LoadInstruction::OutIsr,
+                18 => LoadInstruction::InPins,
+                19 => LoadInstruction::MovOsrIsr,
+                20 => LoadInstruction::Jmp((self.isr & 0x1f) as u8),
+                28 => LoadInstruction::Pull,
+                29 => LoadInstruction::MovXOsr,
+                _ => unreachable!("the program is 31 instructions long"),
+            }),
+        }
+    }
+    /// Run an instruction without advancing the program counter, like `SMx_INSTR`.
+    pub fn exec(&mut self, instruction: LoadInstruction) {
+        match instruction {
+            LoadInstruction::Push => self.isr = 0,
+            LoadInstruction::OutIsr => {
+                self.isr = self.osr & 0b11;
+                self.osr >>= 2;
+            }
+            LoadInstruction::InPins => self.isr = (self.isr << 2) | self.pins,
+            LoadInstruction::MovOsrIsr => self.osr = self.isr,
+            LoadInstruction::EndPulse => {
+                self.osr = self.isr;
+                self.output = false;
+            }
+            LoadInstruction::Pull => self.osr = self.tx.take().unwrap_or(self.x),
+            LoadInstruction::MovXOsr => self.x = self.osr,
+            LoadInstruction::Jmp(address) => self.pc = address,
+        }
+    }
+    /// Replace the armed target the same way the driver does.
+    pub fn load_target(&mut self, target: Step) {
+        for instruction in park(self.pc) {
+            self.exec(*instruction);
+        }
+        self.tx = Some(target.raw().cast_unsigned());
+        for instruction in LOAD {
+            self.exec(instruction);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AFTER_UPDATE, CompareMachine, CompareModel, DISPATCH, FIRE, PitchTargets,
+        TARGET_QUEUE_DEPTH,
+    };
+    use crate::Step;
+
+    fn sweep(model: &mut CompareModel, from: i32, to: i32) -> Vec<i32> {
+        let direction = if to > from { 1 } else { -1 };
+        let mut fired = vec![];
+        let mut step = from;
+        while step != to {
+            step += direction;
+            if model.step_to(Step::new(step)) {
+                fired.push(step);
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn fires_once_per_target() {
+        let mut model = CompareModel::new(Step::new(0), Step::new(5));
+        assert!(model.push(Step::new(8)));
+        assert!(model.push(Step::new(12)));
+        assert_eq!(sweep(&mut model, 0, 20), vec![5, 8, 12]);
+        // Last target stays armed.
+        assert_eq!(model.target(), Step::new(12));
+    }
+
+    #[test]
+    fn fires_in_either_direction() {
+        let mut model = CompareModel::new(Step::new(0), Step::new(-3));
+        model.push(Step::new(2));
+        assert_eq!(sweep(&mut model, 0, -5), vec![-3]);
+        assert_eq!(sweep(&mut model, -5, 5), vec![2]);
+        // Moving back over the last target re-triggers it.
+        assert_eq!(sweep(&mut model, 5, 0), vec![2]);
+    }
+
+    #[test]
+    fn does_not_fire_while_stationary_on_target() {
+        let mut model = CompareModel::new(Step::new(0), Step::new(1));
+        assert!(model.step_to(Step::new(1)));
+        assert!(!model.step_to(Step::new(1)));
+        assert!(!model.step_to(Step::new(1)));
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        let mut model = CompareModel::new(Step::new(0), Step::new(1));
+        for i in 0..TARGET_QUEUE_DEPTH {
+            assert!(model.push(Step::new(i32::try_from(i).unwrap() + 2)));
+        }
+        assert!(!model.push(Step::new(100)));
+    }
+
+    #[test]
+    fn fixed_pitch() {
+        let mut targets = PitchTargets::new(Step::new(10), 25);
+        let mut model = CompareModel::new(Step::new(0), targets.next().unwrap());
+        let mut fired = vec![];
+        let mut step = 0;
+        while step < 200 {
+            // Keep the FIFO topped up like the driver does.
+            while model.queued() < TARGET_QUEUE_DEPTH {
+                model.push(targets.next().unwrap());
+            }
+            step += 1;
+            if model.step_to(Step::new(step)) {
+                fired.push(step);
+            }
+        }
+        assert_eq!(fired, vec![10, 35, 60, 85, 110, 135, 160, 185]);
+
+        let reverse: Vec<_> = PitchTargets::new(Step::new(0), -10)
+            .take(3)
+            .map(|step| step.raw())
+            .collect();
+        assert_eq!(reverse, vec![0, -10, -20]);
+    }
+
+    /// Gray code sequence of pin states when counting up, as `B << 1 | A`.
+    const UP: [u32; 4] = [0b00, 0b10, 0b11, 0b01];
+
+    /// Run long enough for the state machine to see the pins.
+    fn settle(machine: &mut CompareMachine) {
+        for _ in 0..64 {
+            machine.step();
+        }
+    }
+
+    #[test]
+    fn load_target_from_any_instruction() {
+        let mut stopped_at = vec![];
+        for pc in 0..=30 {
+            // Old target 1, stop the state machine at `pc` while stepping onto it.
+            let mut machine = CompareMachine::new(Step::new(1));
+            settle(&mut machine);
+            machine.pins = UP[1];
+            if !(0..64).any(|_| {
+                let reached = machine.pc == pc;
+                if !reached {
+                    machine.step();
+                }
+                reached
+            }) {
+                continue;
+            }
+            stopped_at.push(pc);
+            machine.load_target(Step::new(5));
+            settle(&mut machine);
+            assert_eq!(machine.x, 5, "stopped at {pc}");
+            assert!(!machine.output, "stopped at {pc}");
+            assert_eq!(machine.count(), Step::new(1), "stopped at {pc}");
+
+            // Counting carries on and fires on the new target.
+            machine.irq = false;
+            for (step, pins) in (2..=5).zip(UP.into_iter().cycle().skip(2)) {
+                machine.pins = pins;
+                settle(&mut machine);
+                assert_eq!(machine.count(), Step::new(step), "stopped at {pc}");
+            }
+            assert!(machine.irq, "stopped at {pc}");
+        }
+        for pc in (AFTER_UPDATE..=DISPATCH).chain(FIRE) {
+            assert!(stopped_at.contains(&pc), "never stopped at {pc}");
+        }
+    }
+}
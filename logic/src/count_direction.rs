@@ -0,0 +1,149 @@
+use crate::{Direction, Measurement, Step};
+
+/// Which way the encoder counts up.
+///
+/// Use [`CountDirection::Inverted`] when the encoder is mounted the other way round
+/// or the A/B wires are swapped.
+/// Inversion is applied to the raw PIO readings, so [`Step`], [`SubStep`](crate::SubStep),
+/// [`Speed`](crate::Speed) and [`Direction`] all flip together.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CountDirection {
+    #[default]
+    Normal,
+    Inverted,
+}
+
+impl CountDirection {
+    #[must_use]
+    pub fn invert(&self) -> Self {
+        match self {
+            CountDirection::Normal => CountDirection::Inverted,
+            CountDirection::Inverted => CountDirection::Normal,
+        }
+    }
+    /// Apply the setting to a raw step count.
+    ///
+    /// Steps are mirrored rather than negated (step 0 becomes step -1),
+    /// so that step boundaries still line up with sub-step boundaries.
+    pub fn apply_step(self, step: Step) -> Step {
+        match self {
+            CountDirection::Normal => step,
+            CountDirection::Inverted => step.mirror(),
+        }
+    }
+    pub fn apply_direction(self, direction: Direction) -> Direction {
+        match self {
+            CountDirection::Normal => direction,
+            CountDirection::Inverted => direction.invert(),
+        }
+    }
+    /// Apply the setting to a raw PIO reading.
+    pub fn apply(self, measurement: Measurement) -> Measurement {
+        Measurement {
+            step: self.apply_step(measurement.step),
+            direction: self.apply_direction(measurement.direction),
+            ..measurement
+        }
+    }
+}
+
+/// Suggests a [`CountDirection`] by comparing a commanded motion against what the encoder saw.
+///
+/// Create the detector, command a move in a known direction, then call [`Self::suggest`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DirectionDetector {
+    commanded: Direction,
+    current: CountDirection,
+    start: Step,
+    min_steps: u32,
+}
+
+impl DirectionDetector {
+    /// `start` must be read with `current` applied.
+    pub fn new(commanded: Direction, current: CountDirection, start: Step) -> Self {
+        Self {
+            commanded,
+            current,
+            start,
+            min_steps: 4,
+        }
+    }
+    /// Ignore motion smaller than `min_steps`, to avoid being fooled by vibration.
+    /// Defaults to one full cycle (4 steps).
+    #[must_use]
+    pub fn with_min_steps(mut self, min_steps: u32) -> Self {
+        self.min_steps = min_steps;
+        self
+    }
+    /// The setting that makes the encoder agree with the commanded direction,
+    /// or `None` if the encoder has not moved far enough to tell.
+    pub fn suggest(&self, now: Step) -> Option<CountDirection> {
+        let travel = now.raw().wrapping_sub(self.start.raw());
+        if travel.unsigned_abs() < self.min_steps {
+            return None;
+        }
+        let observed = self.start.comp(now)?;
+        Some(if observed == self.commanded {
+            self.current
+        } else {
+            self.current.invert()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CountDirection, DirectionDetector};
+    use crate::{Direction, EQUAL_STEPS, Step};
+
+    #[test]
+    fn mirrored_steps_line_up() {
+        let inverted = CountDirection::Inverted;
+        for step in [-5, -1, 0, 1, 7] {
+            let range = Step::new(step).substep_range(&EQUAL_STEPS);
+            let mirrored = inverted
+                .apply_step(Step::new(step))
+                .substep_range(&EQUAL_STEPS);
+            assert_eq!(mirrored.start.raw(), -range.end.raw());
+            assert_eq!(mirrored.end.raw(), -range.start.raw());
+        }
+        assert_eq!(
+            CountDirection::Normal.apply_step(Step::new(3)),
+            Step::new(3)
+        );
+        assert_eq!(
+            inverted.apply_direction(Direction::Clockwise),
+            Direction::CounterClockwise
+        );
+    }
+
+    #[test]
+    fn detect_direction() {
+        let detector = DirectionDetector::new(
+            Direction::CounterClockwise,
+            CountDirection::Normal,
+            Step::new(10),
+        );
+        assert_eq!(detector.suggest(Step::new(12)), None);
+        assert_eq!(
+            detector.suggest(Step::new(20)),
+            Some(CountDirection::Normal)
+        );
+        assert_eq!(
+            detector.suggest(Step::new(0)),
+            Some(CountDirection::Inverted)
+        );
+
+        // Readings already inverted that still disagree flip back to normal.
+        let detector =
+            DirectionDetector::new(Direction::Clockwise, CountDirection::Inverted, Step::new(0))
+                .with_min_steps(1);
+        assert_eq!(detector.suggest(Step::new(1)), Some(CountDirection::Normal));
+        assert_eq!(
+            detector.suggest(Step::new(-1)),
+            Some(CountDirection::Inverted)
+        );
+    }
+}
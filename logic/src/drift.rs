@@ -0,0 +1,141 @@
+use embassy_time::Duration;
+
+use crate::{Measurement, PioTiming};
+
+/// How much embassy time has to be observed before [`DriftMonitor`] reports a drift.
+///
+/// Sample instants only have microsecond resolution, this keeps the error below 10 ppm.
+pub const MIN_DRIFT_OBSERVATION: Duration = Duration::from_millis(100);
+
+/// Cross-checks the PIO time base against [`embassy_time::Instant`].
+///
+/// While the encoder stays on a step the PIO transition timer and the sample instants should
+/// advance by the same amount. If they do not, the clock the PIO timing assumes is wrong,
+/// e.g. the system clock was changed without telling the encoder.
+///
+/// Feed every [`Measurement`] to [`Self::record`], stretches where the encoder moved are skipped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DriftMonitor {
+    previous: Option<Measurement>,
+    pio_nanos: u64,
+    embassy_nanos: u64,
+}
+
+impl Default for DriftMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DriftMonitor {
+    pub const fn new() -> Self {
+        Self {
+            previous: None,
+            pio_nanos: 0,
+            embassy_nanos: 0,
+        }
+    }
+    /// Compare `measurement` against the previous one.
+    pub fn record(&mut self, measurement: Measurement) {
+        if let Some(previous) = self.previous.replace(measurement)
+            && previous.step == measurement.step
+            && previous.direction == measurement.direction
+            && measurement.sample_instant > previous.sample_instant
+        {
+            let pio = measurement.nanos_since_transition();
+            let since = previous.nanos_since_transition();
+            // A smaller value means the timer wrapped or a transition was missed.
+            if pio >= since {
+                self.pio_nanos = self.pio_nanos.saturating_add(pio - since);
+                self.embassy_nanos = self.embassy_nanos.saturating_add(
+                    (measurement.sample_instant - previous.sample_instant).as_micros() * 1000,
+                );
+            }
+        }
+    }
+    /// Forget what was observed, e.g. after the PIO timing was changed.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+    /// Embassy time observed while the encoder was still.
+    pub fn observed(&self) -> Duration {
+        Duration::from_micros(self.embassy_nanos / 1000)
+    }
+    /// How much faster the PIO time base runs than embassy time, in parts per million.
+    ///
+    /// `None` until [`MIN_DRIFT_OBSERVATION`] has been observed.
+    pub fn drift_ppm(&self) -> Option<i64> {
+        if self.observed() < MIN_DRIFT_OBSERVATION {
+            return None;
+        }
+        let difference = i128::from(self.pio_nanos) - i128::from(self.embassy_nanos);
+        let ppm = difference * 1_000_000 / i128::from(self.embassy_nanos);
+        Some(i64::try_from(ppm).unwrap_or(i64::MAX))
+    }
+    /// Whether the drift is known to be larger than `tolerance_ppm` in either direction.
+    pub fn exceeds(&self, tolerance_ppm: u32) -> bool {
+        self.drift_ppm()
+            .is_some_and(|ppm| ppm.unsigned_abs() > u64::from(tolerance_ppm))
+    }
+    /// `timing` with its clock adjusted so the PIO time base matches embassy time.
+    ///
+    /// `None` until [`MIN_DRIFT_OBSERVATION`] has been observed.
+    pub fn corrected(&self, timing: &PioTiming) -> Option<PioTiming> {
+        self.drift_ppm()?;
+        // The PIO counted `pio_nanos` at the assumed clock in `embassy_nanos` of real time.
+        let clock_hz = u128::from(timing.clock_hz()) * u128::from(self.pio_nanos)
+            / u128::from(self.embassy_nanos);
+        Some(timing.with_clock_hz(u32::try_from(clock_hz).unwrap_or(u32::MAX)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DriftMonitor;
+    use crate::{DirectionDuration, Measurement, PioTiming, Step};
+    use embassy_time::Instant;
+
+    /// What the PIO reports `elapsed_ms` into a stretch without steps, if it runs at `actual_hz`.
+    fn reading(timing: &PioTiming, actual_hz: u64, elapsed_ms: u64) -> Measurement {
+        let loops = actual_hz * elapsed_ms / 1000 / 13;
+        let (direction, nanos) =
+            DirectionDuration::new(i32::MIN.wrapping_sub(i32::try_from(loops).unwrap()))
+                .decode_nanos(timing);
+        Measurement::from_nanos(
+            direction,
+            Step::new(3),
+            Instant::from_millis(elapsed_ms),
+            nanos,
+        )
+    }
+
+    #[test]
+    fn matching_clocks() {
+        let timing = PioTiming::new(125);
+        let mut monitor = DriftMonitor::new();
+        monitor.record(reading(&timing, 125_000_000, 10));
+        monitor.record(reading(&timing, 125_000_000, 50));
+        // Not enough time observed yet.
+        assert_eq!(monitor.drift_ppm(), None);
+        monitor.record(reading(&timing, 125_000_000, 200));
+        assert!(!monitor.exceeds(10));
+    }
+
+    #[test]
+    fn detects_and_corrects_a_clock_change() {
+        // Firmware dropped the clock to 100 MHz behind the encoder's back.
+        let timing = PioTiming::new(125);
+        let mut monitor = DriftMonitor::new();
+        for elapsed_ms in [10, 60, 110, 160] {
+            monitor.record(reading(&timing, 100_000_000, elapsed_ms));
+        }
+        assert!(monitor.exceeds(100_000));
+        let drift = monitor.drift_ppm().unwrap();
+        assert!((-200_010..=-199_990).contains(&drift), "{drift}");
+        let corrected = monitor.corrected(&timing).unwrap();
+        assert!(corrected.clock_hz().abs_diff(100_000_000) < 1000);
+        monitor.reset();
+        assert_eq!(monitor.drift_ppm(), None);
+    }
+}
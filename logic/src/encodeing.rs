@@ -1,4 +1,5 @@
 use super::Direction;
+use crate::Step;
 use embassy_time::Duration;
 
 /// The number of clock cycles it takes for the pio loop to for one iteration.
@@ -6,11 +7,15 @@ use embassy_time::Duration;
 /// The pio program always takes 13 clock cycles for each loop.
 const LOOP_DURATION: u32 = 13;
 
+const MICROS_PER_SECOND: u64 = 1_000_000;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
 /// Contains the direction of the last encoder tick and how long ago that happened.
 ///
 /// This encoding works by splitting the i32 in half.
 /// - Counterclockwise range = [0, `i32::MIN`)
 /// - Clockwise range = [`i32::MIN`,0)
+///
 /// When a tick is register the counter is reset to the top of its respective range (Clockwise or
 /// Counterclockwise)
 /// After every subsequent loop if a step was not detected we decrement the counter.
@@ -19,11 +24,8 @@ const LOOP_DURATION: u32 = 13;
 /// In that case the direction will flip and the duration will reset to zero.
 /// This happens after about 3.5 minutes (assuming 125Mhz clock speed)
 ///
-/// However, overflows will not cause any faulty readings.
-/// The duration is not used in speed calculations if the encoder is in a stopped state.
-/// The caller of the crate is responsible for repeatedly calling the update function,
-/// (10hz at least)
-/// so we will always be in a stopped state before an overflow could occur.
+/// [`EncoderState`](crate::EncoderState) detects these wraps and keeps the time since the
+/// transition counting, see [`Measurement::extend_across_wraps`](crate::Measurement::extend_across_wraps).
 /// Represents the encoders current direction and how many PIO loop has run since the last step.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct DirectionDuration(pub i32);
@@ -42,6 +44,39 @@ impl DirectionDuration {
     }
     /// Split into direction and duration.
     pub fn decode(self, clock_ticks_per_us: u32) -> (Direction, Duration) {
+        self.decode_with_loop_duration(clock_ticks_per_us, LOOP_DURATION)
+    }
+    /// Same as [`Self::decode`] for PIO programs whose loop does not take 13 clock cycles.
+    pub fn decode_with_loop_duration(
+        self,
+        clock_ticks_per_us: u32,
+        loop_duration: u32,
+    ) -> (Direction, Duration) {
+        self.decode_with_timing(
+            &PioTiming::new(clock_ticks_per_us).with_loop_duration(loop_duration),
+        )
+    }
+    /// Same as [`Self::decode`] for any clock divider and loop duration.
+    pub fn decode_with_timing(self, timing: &PioTiming) -> (Direction, Duration) {
+        let (direction, cycles) = self.cycles(timing);
+        // By the time we have hit u32::Max cycles the encoder should be in a stopped state.
+        // So saturating here should not affect anything.
+        let cycles = cycles.min(u32::MAX.into());
+        (
+            direction,
+            Duration::from_micros(timing.scale(cycles, MICROS_PER_SECOND)),
+        )
+    }
+    /// Same as [`Self::decode_with_timing`] but in nanoseconds,
+    /// keeping the resolution of the PIO loop rather than rounding down to whole microseconds.
+    ///
+    /// This does not saturate, it keeps counting until the timer itself wraps.
+    pub fn decode_nanos(self, timing: &PioTiming) -> (Direction, u64) {
+        let (direction, cycles) = self.cycles(timing);
+        (direction, timing.scale(cycles, NANOS_PER_SECOND))
+    }
+    /// Direction and the number of PIO cycles since the last transition.
+    fn cycles(self, timing: &PioTiming) -> (Direction, u64) {
         let direction = if self.0 < 0 {
             Direction::CounterClockwise
         } else {
@@ -57,18 +92,218 @@ impl DirectionDuration {
                 iterations as u32
             }
         };
+        // A filtered program registers the transition this long after it happened.
+        (
+            direction,
+            u64::from(iterations) * u64::from(timing.loop_duration)
+                + u64::from(timing.filter_cycles),
+        )
+    }
+}
 
-        // By the time we have hit u32::Max cycles the encoder should be in a stopped state.
-        // So saturating here should not affect anything (aside from preventing an overflow).
-        let cycles = (iterations).saturating_mul(LOOP_DURATION);
-        let duration = Duration::from_micros((cycles / clock_ticks_per_us).into());
-        (direction, duration)
+/// Step count and invalid transition count read from the error counting step program.
+///
+/// The top 8 bits hold the low bits of a counter that decrements on every invalid transition,
+/// the low 24 bits hold the low bits of the step count.
+/// Both are truncated, [`Self::extend_step`] and [`Self::extend_errors`] recover the full counts
+/// as long as they are read before the step count moves by 2^23 or 256 invalid transitions happen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PackedCount(pub u32);
+
+/// Bits of the step count that fit in a [`PackedCount`].
+const PACKED_STEP_BITS: u32 = 24;
+
+impl PackedCount {
+    pub fn new(val: u32) -> Self {
+        Self(val)
+    }
+    /// Step count, sign extended from 24 bits.
+    pub fn step(self) -> Step {
+        let shift = 32 - PACKED_STEP_BITS;
+        Step::new((self.0 << shift).cast_signed() >> shift)
+    }
+    /// Number of invalid transitions, modulo 256.
+    pub fn errors(self) -> u8 {
+        let counter = (self.0 >> PACKED_STEP_BITS) as u8;
+        counter.wrapping_neg()
+    }
+    /// The full step count, given the full count at the `previous` read.
+    #[must_use]
+    pub fn extend_step(self, previous: Step) -> Step {
+        let delta = PackedCount(
+            self.step()
+                .raw()
+                .wrapping_sub(previous.raw())
+                .cast_unsigned(),
+        );
+        Step::new(previous.raw().wrapping_add(delta.step().raw()))
+    }
+    /// The full number of invalid transitions, given the full number at the `previous` read.
+    #[must_use]
+    pub fn extend_errors(self, previous: u32) -> u32 {
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "only the low bits are compared"
+        )]
+        let delta = self.errors().wrapping_sub(previous as u8);
+        previous.wrapping_add(delta.into())
+    }
+}
+
+/// PIO clock divider, in the same 16.8 fixed point format as the hardware register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClockDivider {
+    bits: u32,
+}
+
+impl ClockDivider {
+    const FRACTIONAL_BITS: u32 = 8;
+    /// The PIO runs at the system clock.
+    pub const NONE: Self = Self::new(1, 0);
+    /// Divide by `integer + fraction / 256`.
+    ///
+    /// # Panics
+    /// If the divider is below 1.
+    pub const fn new(integer: u16, fraction: u8) -> Self {
+        assert!(
+            integer >= 1,
+            "The PIO can not run faster than the system clock"
+        );
+        Self {
+            bits: ((integer as u32) << Self::FRACTIONAL_BITS) | fraction as u32,
+        }
+    }
+    /// Divide by a whole number.
+    pub const fn integer(integer: u16) -> Self {
+        Self::new(integer, 0)
+    }
+    /// The raw 16.8 fixed point value.
+    pub const fn to_bits(&self) -> u32 {
+        self.bits
+    }
+}
+
+impl Default for ClockDivider {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// How fast a PIO program loops, used to decode [`DirectionDuration`]s.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PioTiming {
+    clock_hz: u32,
+    divider: ClockDivider,
+    loop_duration: u32,
+    filter_cycles: u32,
+}
+
+/// What a [`PioTiming`] can measure.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimingTradeoffs {
+    /// The PIO samples the pins once per loop, so this is the fastest it can count.
+    pub max_step_rate_hz: u32,
+    /// Time between samples, the resolution of transition times.
+    pub resolution_ns: u32,
+    /// How long the transition timer runs before it wraps.
+    pub overflow_window: Duration,
+}
+
+impl PioTiming {
+    /// `clock_ticks_per_us` is the system clock in MHz, the divider defaults to 1 and the loop to 13 cycles.
+    pub const fn new(clock_ticks_per_us: u32) -> Self {
+        Self::from_hz(clock_ticks_per_us.saturating_mul(1_000_000))
+    }
+    /// Same as [`Self::new`] with the clock in Hz, so clocks that are not a whole number of MHz
+    /// are not rounded.
+    pub const fn from_hz(clock_hz: u32) -> Self {
+        Self {
+            clock_hz,
+            divider: ClockDivider::NONE,
+            loop_duration: LOOP_DURATION,
+            filter_cycles: 0,
+        }
+    }
+    #[must_use]
+    pub const fn with_divider(mut self, divider: ClockDivider) -> Self {
+        self.divider = divider;
+        self
+    }
+    #[must_use]
+    pub const fn with_loop_duration(mut self, loop_duration: u32) -> Self {
+        self.loop_duration = loop_duration;
+        self
+    }
+    /// A glitch filter that needs a pin change to be stable for `filter_loops` loops of
+    /// `filter_loop_cycles` each delays every transition by that long,
+    /// decoded durations add the delay back on.
+    ///
+    /// The filter loop is usually shorter than the main loop, so it is given separately.
+    #[must_use]
+    pub const fn with_filter_loops(mut self, filter_loops: u32, filter_loop_cycles: u32) -> Self {
+        self.filter_cycles = filter_loops.saturating_mul(filter_loop_cycles);
+        self
+    }
+    /// PIO cycles a filter delays each transition by, 0 without a filter.
+    pub const fn filter_cycles(&self) -> u32 {
+        self.filter_cycles
+    }
+    /// How long a filter delays each transition by.
+    pub fn filter_delay(&self) -> Duration {
+        Duration::from_micros(self.scale(self.filter_cycles.into(), MICROS_PER_SECOND))
+    }
+    pub const fn divider(&self) -> ClockDivider {
+        self.divider
+    }
+    pub const fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+    /// Change the system clock, keeping the divider, loop duration and filter.
+    #[must_use]
+    pub const fn with_clock_hz(mut self, clock_hz: u32) -> Self {
+        self.clock_hz = clock_hz;
+        self
+    }
+    /// Length of one loop in 1/256ths of a system clock cycle.
+    fn loop_fraction_cycles(&self) -> u64 {
+        u64::from(self.loop_duration) * u64::from(self.divider.to_bits())
+    }
+    /// Convert system clock cycles, stretched by the divider, into `units_per_second`.
+    fn scale(&self, cycles: u64, units_per_second: u64) -> u64 {
+        self.fraction_cycles_to(
+            u128::from(cycles) * u128::from(self.divider.to_bits()),
+            units_per_second,
+        )
+    }
+    /// Uses the exact clock frequency so there is no rounding until the final division.
+    fn fraction_cycles_to(&self, fraction_cycles: u128, units_per_second: u64) -> u64 {
+        let units = fraction_cycles * u128::from(units_per_second)
+            / (u128::from(self.clock_hz.max(1)) << ClockDivider::FRACTIONAL_BITS);
+        u64::try_from(units).unwrap_or(u64::MAX)
+    }
+    pub fn tradeoffs(&self) -> TimingTradeoffs {
+        let loop_fraction_cycles = self.loop_fraction_cycles();
+        let max_step_rate_hz =
+            (u64::from(self.clock_hz) << ClockDivider::FRACTIONAL_BITS) / loop_fraction_cycles;
+        let resolution_ns = self.fraction_cycles_to(loop_fraction_cycles.into(), NANOS_PER_SECOND);
+        // The timer counts down from the top of its half of the i32 range.
+        let overflow_micros =
+            self.fraction_cycles_to(u128::from(loop_fraction_cycles) << 31, MICROS_PER_SECOND);
+        TimingTradeoffs {
+            max_step_rate_hz: u32::try_from(max_step_rate_hz).unwrap_or(u32::MAX),
+            resolution_ns: u32::try_from(resolution_ns).unwrap_or(u32::MAX),
+            overflow_window: Duration::from_micros(overflow_micros),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Direction, DirectionDuration};
+    use super::{ClockDivider, Direction, DirectionDuration, PackedCount, PioTiming};
+    use crate::Step;
     use crate::encodeing::{LOOP_DURATION, loop_count_start};
     use embassy_time::Duration;
 
@@ -87,6 +322,11 @@ mod tests {
     /// count)
     ///
     #[test]
+    #[expect(
+        clippy::doc_markdown,
+        clippy::cast_lossless,
+        reason = "Upstream test, kept as written"
+    )]
     fn zero_cycles_underflow() {
         for direction in [Direction::Clockwise, Direction::CounterClockwise] {
             // Lowest values in x direction.
@@ -103,6 +343,7 @@ mod tests {
     }
 
     #[test]
+    #[expect(clippy::cast_possible_wrap, reason = "Upstream test, kept as written")]
     fn decode() {
         for direction in [Direction::Clockwise, Direction::CounterClockwise] {
             for ticks_per_ms in [1, 5, 10] {
@@ -122,4 +363,140 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn exact_clock_ratio() {
+        let one_loop = DirectionDuration(loop_count_start(Direction::Clockwise).wrapping_sub(1));
+        assert_eq!(
+            one_loop.decode_nanos(&PioTiming::new(125)),
+            (Direction::Clockwise, 104)
+        );
+        let value =
+            DirectionDuration(loop_count_start(Direction::Clockwise).wrapping_sub(1_000_000));
+        let timing = PioTiming::from_hz(132_500_000);
+        // Rounding the clock to 133 MHz would give 97_744us.
+        assert_eq!(
+            value.decode_with_timing(&timing),
+            (Direction::Clockwise, Duration::from_micros(98_113))
+        );
+        assert_eq!(
+            value.decode_nanos(&timing),
+            (Direction::Clockwise, 98_113_207)
+        );
+    }
+
+    #[test]
+    fn divider_stretches_durations() {
+        let loops = 1000;
+        let value = DirectionDuration(loop_count_start(Direction::Clockwise).wrapping_sub(loops));
+        let timing = PioTiming::new(125);
+        assert_eq!(value.decode_with_timing(&timing), value.decode(125));
+        assert_eq!(
+            value.decode_with_timing(&timing.with_divider(ClockDivider::integer(4))),
+            (Direction::Clockwise, Duration::from_micros(416))
+        );
+        // 2.5
+        assert_eq!(
+            value.decode_with_timing(&timing.with_divider(ClockDivider::new(2, 128))),
+            (Direction::Clockwise, Duration::from_micros(260))
+        );
+    }
+
+    #[test]
+    fn filter_latency() {
+        let value = DirectionDuration(loop_count_start(Direction::Clockwise).wrapping_sub(1000));
+        let timing = PioTiming::new(125);
+        // 4 filter loops of 10 cycles on top of 1000 loops of 13 cycles.
+        let filtered = timing.with_filter_loops(4, 10);
+        assert_eq!(filtered.filter_cycles(), 40);
+        assert_eq!(
+            value.decode_nanos(&filtered),
+            (Direction::Clockwise, 104_320)
+        );
+        // The delay is measured in filter loops, not main loops.
+        let one_loop = DirectionDuration(loop_count_start(Direction::Clockwise).wrapping_sub(1));
+        assert_eq!(
+            one_loop.decode_nanos(&timing.with_filter_loops(13, 10)),
+            DirectionDuration(loop_count_start(Direction::Clockwise).wrapping_sub(11))
+                .decode_nanos(&timing)
+        );
+        // 31 loops of 41 cycles, the longest step program filter.
+        assert_eq!(
+            timing
+                .with_divider(ClockDivider::integer(2))
+                .with_filter_loops(31, 41)
+                .filter_delay(),
+            Duration::from_micros(20)
+        );
+    }
+
+    #[test]
+    fn tradeoffs() {
+        let report = PioTiming::new(125).tradeoffs();
+        assert_eq!(report.max_step_rate_hz, 9_615_384);
+        assert_eq!(report.resolution_ns, 104);
+        // About 3.5 minutes.
+        assert_eq!(report.overflow_window.as_secs(), 223);
+
+        let report = PioTiming::new(125)
+            .with_divider(ClockDivider::integer(10))
+            .tradeoffs();
+        assert_eq!(report.max_step_rate_hz, 961_538);
+        assert_eq!(report.resolution_ns, 1040);
+        assert_eq!(report.overflow_window.as_secs(), 2233);
+    }
+
+    #[test]
+    fn packed_count() {
+        // One invalid transition, step -2.
+        let packed = PackedCount::new(0xff_ff_ff_fe);
+        assert_eq!(packed.step(), Step::new(-2));
+        assert_eq!(packed.errors(), 1);
+        let packed = PackedCount::new(0x00_7f_ff_ff);
+        assert_eq!(packed.step(), Step::new(0x7f_ff_ff));
+        assert_eq!(packed.errors(), 0);
+    }
+
+    #[test]
+    fn packed_count_extends_across_truncation() {
+        // The step count went from 2^23 - 1 to 2^23 + 1, which reads as -2^23 + 1.
+        let previous = Step::new(0x7f_ff_ff);
+        let packed = PackedCount::new(0x00_80_00_01);
+        assert_eq!(packed.extend_step(previous), Step::new(0x80_00_01));
+        // And back down across zero.
+        assert_eq!(
+            PackedCount::new(0x00_ff_ff_ff).extend_step(Step::new(0x1_00_00_01)),
+            Step::new(0xff_ff_ff)
+        );
+        assert_eq!(
+            PackedCount::new(0x00_ff_ff_ff).extend_step(Step::new(2)),
+            Step::new(-1)
+        );
+        // 300 errors so far, the counter has wrapped past zero once.
+        let counter = 0u8.wrapping_sub(44);
+        let packed = PackedCount::new(u32::from(counter) << 24);
+        assert_eq!(packed.errors(), 44);
+        assert_eq!(packed.extend_errors(250), 300);
+        assert_eq!(packed.extend_errors(300), 300);
+    }
+
+    #[test]
+    fn packed_step_wraps_every_2_pow_24() {
+        // Follow a count that keeps moving forward by just under half the packed range,
+        // it wraps the 24 bits several times and then the i32 itself.
+        let mut full = Step::new(i32::MAX - (5 << 22));
+        let mut count = full.raw();
+        for _ in 0..10 {
+            count = count.wrapping_add((1 << 23) - 1);
+            let packed = PackedCount::new(count.cast_unsigned() & 0x00_ff_ff_ff);
+            full = packed.extend_step(full);
+            assert_eq!(full, Step::new(count));
+        }
+        // Moving 2^23 steps or more between reads aliases to the other direction.
+        let previous = Step::new(0);
+        let packed = PackedCount::new(1 << 23);
+        assert_eq!(packed.extend_step(previous), Step::new(-(1 << 23)));
+        let packed = PackedCount::new((1 << 24) + 5);
+        assert_eq!(packed.extend_step(previous), Step::new(5));
+    }
 }
@@ -0,0 +1,92 @@
+use embassy_time::Duration;
+
+use crate::{Measurement, Step};
+
+/// How far before the previous sample a new transition may appear to have happened.
+///
+/// The sample instant is taken just after the words are pulled, so a step that lands between
+/// the PIO push and the timestamp looks slightly older than the previous sample.
+pub const DESYNC_TOLERANCE: Duration = Duration::from_micros(10);
+
+/// Why a reading was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UpdateError {
+    /// The state machine did not push a reading in time, it may be stalled or disabled.
+    FifoTimeout,
+    /// The step count changed but the transition time predates the previous reading,
+    /// the two words of the reading most likely came from different samples.
+    /// Also reported when the first reading after start-up fails [`check_sync`](crate::check_sync).
+    Desynchronized,
+    /// The step count moved further than the configured limit since the previous reading.
+    ImplausibleJump { steps: i32 },
+    /// The reading is not newer than the previous one.
+    Stale,
+}
+
+/// Check a new reading against the previous one.
+///
+/// `max_step_jump` is the largest step change accepted between two readings.
+/// # Errors
+/// The first check the new reading fails.
+pub fn classify(
+    previous: &Measurement,
+    current: &Measurement,
+    max_step_jump: u32,
+) -> Result<(), UpdateError> {
+    if current.sample_instant <= previous.sample_instant {
+        return Err(UpdateError::Stale);
+    }
+    let steps = step_delta(previous.step, current.step);
+    if steps.unsigned_abs() > max_step_jump {
+        return Err(UpdateError::ImplausibleJump { steps });
+    }
+    if steps != 0 && current.step_instant + DESYNC_TOLERANCE < previous.sample_instant {
+        return Err(UpdateError::Desynchronized);
+    }
+    Ok(())
+}
+
+fn step_delta(previous: Step, current: Step) -> i32 {
+    current.raw().wrapping_sub(previous.raw())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UpdateError, classify};
+    use crate::{Direction, Measurement, Step};
+    use embassy_time::{Duration, Instant};
+
+    fn reading(step: i32, sample_ms: u64, since_transition_ms: u64) -> Measurement {
+        Measurement::new(
+            Direction::CounterClockwise,
+            Step::new(step),
+            Instant::from_millis(sample_ms),
+            Duration::from_millis(since_transition_ms),
+        )
+    }
+
+    #[test]
+    fn classify_readings() {
+        let previous = reading(10, 100, 5);
+        assert_eq!(classify(&previous, &reading(12, 110, 3), 8), Ok(()));
+        // Nothing moved, an old transition time is expected.
+        assert_eq!(classify(&previous, &reading(10, 110, 15), 8), Ok(()));
+        assert_eq!(
+            classify(&previous, &reading(12, 100, 3), 8),
+            Err(UpdateError::Stale)
+        );
+        assert_eq!(
+            classify(&previous, &reading(-10, 110, 3), 8),
+            Err(UpdateError::ImplausibleJump { steps: -20 })
+        );
+        // Stepped, but the transition happened before the previous reading.
+        assert_eq!(
+            classify(&previous, &reading(11, 110, 20), 8),
+            Err(UpdateError::Desynchronized)
+        );
+        // Wrapping around the step counter is a small move.
+        let previous = reading(i32::MAX, 100, 5);
+        assert_eq!(classify(&previous, &reading(i32::MIN, 110, 3), 8), Ok(()));
+    }
+}
@@ -5,14 +5,49 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::must_use_candidate)]
 use embassy_time::Duration;
+mod adapters;
+pub use adapters::{EncoderExt, FilterConfig, Filtered, Inverted, Offset, Scaled};
+mod angle;
+pub use angle::{Angle, AngularDistance, GearRatio, RotaryConfig};
+mod backlash;
+pub use backlash::{BacklashConfig, BacklashEstimator, Engagement};
+pub mod compare;
+mod count_direction;
+pub use count_direction::{CountDirection, DirectionDetector};
+mod drift;
+pub use drift::{DriftMonitor, MIN_DRIFT_OBSERVATION};
 pub mod encodeing;
+mod error;
+pub use error::{DESYNC_TOLERANCE, UpdateError, classify};
+mod linear;
+pub use linear::{LinearAxis, LinearConfig, LinearSpeed, Micrometers};
 mod speed;
 pub use speed::Speed;
 mod measurement;
-pub use encodeing::DirectionDuration;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod odometry;
+pub use odometry::{DifferentialDrive, OdometryConfig, Pose, Twist};
+mod phase;
+pub use phase::{SyncError, check_sync, initial_step, phase};
+mod probe;
+mod sampling;
+pub use sampling::{JitterStats, MAX_UPDATE_PERIOD, RateWarning, check_period};
+mod snapshot;
+pub use encodeing::{ClockDivider, DirectionDuration, PackedCount, PioTiming, TimingTradeoffs};
 pub use measurement::Measurement;
+pub use probe::{PROBE_LOOP_DURATION, ProbeCapture};
+pub use snapshot::Snapshot;
 mod step;
 pub use step::{Step, SubStep};
+mod wait;
+pub use wait::WaitFor;
+mod window_speed;
+pub use window_speed::WindowSpeed;
+mod wiring;
+pub use wiring::{PullTest, WiringFault, WiringMonitor};
+mod zones;
+pub use zones::{Limit, MonitorEvent, SoftLimits, Zone, ZoneEvent, ZoneEvents, ZoneMonitor};
 
 type CalibrationData = [u8; 4];
 /// Default calibration value that assumes each encoder tick is the same size
@@ -20,11 +55,13 @@ const EQUAL_STEPS: CalibrationData = [0, 64, 128, 192];
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Direction {
     Clockwise,
     CounterClockwise,
 }
 impl Direction {
+    #[must_use]
     pub fn invert(&self) -> Self {
         match self {
             Direction::Clockwise => Direction::CounterClockwise,
@@ -36,10 +73,15 @@ impl Direction {
 /// Stores all the logical state required for the sub-step encoder.
 ///
 ///NOTE: this intentionally does not rely on `embasy_rp` as that would prevent me from running the unit tests on my host machine.
+#[derive(Clone)]
 pub struct EncoderState<const IDLE_STOPING_TIME_MS: u64> {
     calibration_data: CalibrationData,
     last_known_speed: Speed,
     prev_measurement: Measurement,
+    unwrapped_position: i64,
+    count_direction: CountDirection,
+    max_step_jump: u32,
+    timer_wrap: Duration,
 }
 impl<const IDLE_STOPING_TIME_MS: u64> EncoderState<IDLE_STOPING_TIME_MS> {
     /// Get current encoder speed
@@ -49,18 +91,117 @@ impl<const IDLE_STOPING_TIME_MS: u64> EncoderState<IDLE_STOPING_TIME_MS> {
     /// Get last estimated position in subsets
     pub fn position(&self) -> SubStep {
         self.prev_measurement.transition(&self.calibration_data)
-            + self.last_known_speed * (self.prev_measurement.time_since_transition())
+            + self
+                .last_known_speed
+                .mul_nanos(self.prev_measurement.nanos_since_transition())
     }
     /// Get the current encoder step
     pub fn steps(&self) -> Step {
         self.prev_measurement.step
     }
+    /// Same as [`Self::position`] but does not wrap when the sub-step counter overflows.
+    pub fn unwrapped_position(&self) -> i64 {
+        self.unwrapped_position
+    }
+    /// Unwrapped sub-step position of the most recent transition.
+    pub fn unwrapped_transition(&self) -> i64 {
+        let transition = self.prev_measurement.transition(&self.calibration_data);
+        self.unwrapped_position - i64::from((self.position() - transition).raw())
+    }
+    /// The most recent measurement passed to [`Self::update`].
+    pub fn last_measurement(&self) -> Measurement {
+        self.prev_measurement
+    }
+    /// Everything known about the encoder, taken from the most recent measurement.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            step: self.steps(),
+            position: self.position(),
+            speed: self.last_known_speed,
+            direction: self.prev_measurement.direction,
+            since_transition: self.prev_measurement.time_since_transition(),
+            sample_instant: self.prev_measurement.sample_instant,
+        }
+    }
+    /// Get the last estimated position as an angle of a rotary axis.
+    pub fn angle(&self, config: &RotaryConfig) -> Angle {
+        config.angle(self.unwrapped_position)
+    }
+    /// Get the last estimated position along a linear axis.
+    pub fn linear_position(&self, config: &LinearConfig) -> Micrometers {
+        config.to_micrometers(self.unwrapped_position)
+    }
+    /// Get current encoder speed along a linear axis.
+    pub fn linear_speed(&self, config: &LinearConfig) -> LinearSpeed {
+        config.to_linear_speed(self.last_known_speed)
+    }
+    /// Estimate where the encoder was when a probe capture was taken, using the current speed.
+    ///
+    /// Like measurements, the capture is passed raw and the count direction is applied internally.
+    pub fn probe_position(&self, capture: &ProbeCapture) -> SubStep {
+        let capture = ProbeCapture::new(
+            self.count_direction.apply_step(capture.step()),
+            self.count_direction.apply_direction(capture.direction()),
+            capture.since_transition(),
+        );
+        capture.interpolate(self.last_known_speed, &self.calibration_data)
+    }
     pub fn idel_stopping_time() -> Duration {
         Duration::from_millis(IDLE_STOPING_TIME_MS)
     }
 
+    pub fn count_direction(&self) -> CountDirection {
+        self.count_direction
+    }
+
+    /// Reject readings that move more than `max_step_jump` steps since the previous one,
+    /// see [`Self::try_update`]. Unlimited by default.
+    #[must_use]
+    pub fn with_max_step_jump(mut self, max_step_jump: u32) -> Self {
+        self.max_step_jump = max_step_jump;
+        self
+    }
+    pub fn max_step_jump(&self) -> u32 {
+        self.max_step_jump
+    }
+
+    /// How long the PIO transition timer runs before it wraps, see [`Measurement::extend_across_wraps`].
+    ///
+    /// Defaults to the 13 cycle program at 125 MHz, use [`TimingTradeoffs::overflow_window`] for other timings.
+    #[must_use]
+    pub fn with_timer_wrap(mut self, timer_wrap: Duration) -> Self {
+        self.timer_wrap = timer_wrap;
+        self
+    }
+    pub fn timer_wrap(&self) -> Duration {
+        self.timer_wrap
+    }
+
+    /// Process a new reading, unless it fails the checks in [`classify`].
+    ///
+    /// A rejected reading leaves the state untouched.
+    /// # Errors
+    /// The reason the reading was rejected.
+    pub fn try_update(&mut self, measurement: Measurement) -> Result<(), UpdateError> {
+        classify(
+            &self.prev_measurement,
+            &self.prepare(measurement),
+            self.max_step_jump,
+        )?;
+        self.update(measurement);
+        Ok(())
+    }
+
+    /// Apply the count direction and undo timer wraps.
+    fn prepare(&self, measurement: Measurement) -> Measurement {
+        self.count_direction
+            .apply(measurement)
+            .extend_across_wraps(&self.prev_measurement, self.timer_wrap)
+    }
+
     ///Process a new reading.
     pub fn update(&mut self, measurement: Measurement) {
+        let measurement = self.prepare(measurement);
         let new_speed = if measurement.time_since_transition() >= Self::idel_stopping_time() {
             Speed::stopped()
         } else {
@@ -71,40 +212,104 @@ impl<const IDLE_STOPING_TIME_MS: u64> EncoderState<IDLE_STOPING_TIME_MS> {
                 &self.calibration_data,
             )
         };
+        let prev_position = self.position();
         self.last_known_speed = new_speed;
         self.prev_measurement = measurement;
+        // Position only moves a small amount between updates so the wrapping difference is the
+        // true distance traveled.
+        self.unwrapped_position += i64::from((self.position() - prev_position).raw());
     }
 
     ///Initialize a new encoder state.
     pub fn new(inital_conditions: Measurement) -> Self {
+        Self::with_count_direction(inital_conditions, CountDirection::Normal)
+    }
+    /// Initialize a new encoder state that counts in the given direction.
+    ///
+    /// Measurements are passed in raw, the inversion is applied internally.
+    pub fn with_count_direction(
+        inital_conditions: Measurement,
+        count_direction: CountDirection,
+    ) -> Self {
         let calibration_data = EQUAL_STEPS;
-        EncoderState {
+        let mut state = EncoderState {
             calibration_data,
             // set so we start in the stopped state.
             last_known_speed: Speed::stopped(),
-            prev_measurement: inital_conditions,
-        }
+            prev_measurement: count_direction.apply(inital_conditions),
+            unwrapped_position: 0,
+            count_direction,
+            max_step_jump: u32::MAX,
+            timer_wrap: PioTiming::new(125).tradeoffs().overflow_window,
+        };
+        state.unwrapped_position = state.position().raw().into();
+        state
     }
 }
 
 /// A speed encoder
 ///
 /// This trait exists as a seam so that a mock encoder can be injected when unit testing application
-/// code.
+/// code, see `mock::MockEncoder` (requires the `mock` feature).
 pub trait Encoder {
     // Update is used by the encoder to update its internal state.
     // It should be called regularly.
     fn update(&mut self);
+    /// Same as [`Self::update`] but reports readings that could not be used.
+    ///
+    /// Encoders that cannot fail can rely on the default, which always succeeds.
+    /// # Errors
+    /// The reason the reading was rejected, the previous state is kept.
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        self.update();
+        Ok(())
+    }
     fn speed(&self) -> Speed;
     fn position(&self) -> SubStep;
     fn ticks(&self) -> Step;
+    /// Number of illegal transitions (both channels changing at once) seen so far,
+    /// these mean counts were lost to noise or to exceeding the maximum step rate.
+    ///
+    /// `None` if the encoder cannot detect them, which is the default.
+    fn error_count(&self) -> Option<u32> {
+        None
+    }
+    /// When the current readings were sampled.
+    ///
+    /// `None` if the encoder does not know, which is the default.
+    fn sample_instant(&self) -> Option<embassy_time::Instant> {
+        None
+    }
+}
+
+/// Read-only access to an encoder.
+///
+/// Implemented by every [`Encoder`] and by handles to an encoder that is updated elsewhere,
+/// so code that only consumes readings does not need to own the encoder.
+pub trait EncoderReader {
+    fn speed(&self) -> Speed;
+    fn position(&self) -> SubStep;
+    fn ticks(&self) -> Step;
+}
+
+impl<E: Encoder> EncoderReader for E {
+    fn speed(&self) -> Speed {
+        Encoder::speed(self)
+    }
+    fn position(&self) -> SubStep {
+        Encoder::position(self)
+    }
+    fn ticks(&self) -> Step {
+        Encoder::ticks(self)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        Direction::CounterClockwise,
-        EQUAL_STEPS, EncoderState,
+        CountDirection,
+        Direction::{Clockwise, CounterClockwise},
+        EQUAL_STEPS, EncoderState, RotaryConfig, UpdateError,
         measurement::{
             Measurement,
             tests::{Event, sequence_events},
@@ -113,6 +318,7 @@ mod tests {
         step::{Step, SubStep},
     };
     use embassy_time::{Duration, Instant};
+    #[expect(clippy::useless_conversion, reason = "Upstream test, kept as written")]
     fn simulate_assert(
         measurements: Vec<Measurement>,
         speeds: Vec<Speed>,
@@ -283,6 +489,10 @@ mod tests {
         simulate_assert(measurements, speeds, positions);
     }
     #[test]
+    #[expect(
+        clippy::zero_prefixed_literal,
+        reason = "Upstream test, kept as written"
+    )]
     fn always_use_larger_delta_speed_for_estiments() {
         let measurements = sequence_events(
             (Step::new(0), CounterClockwise, Instant::from_millis(0)),
@@ -334,4 +544,180 @@ mod tests {
         ];
         simulate_assert(measurements, speeds, positions);
     }
+
+    #[test]
+    fn unwrapped_position_survives_substep_overflow() {
+        // Sub-steps overflow once every 2^26 steps.
+        let overflow = 1 << 26;
+        let measurements = sequence_events(
+            (
+                Step::new(overflow - 2),
+                CounterClockwise,
+                Instant::from_millis(0),
+            ),
+            vec![
+                (Instant::from_millis(0), Event::Mesurement),
+                (Instant::from_millis(10), Event::Step(overflow + 2)),
+                (Instant::from_millis(10), Event::Mesurement),
+                (Instant::from_millis(20), Event::Step(overflow - 2)),
+                (Instant::from_millis(20), Event::Mesurement),
+            ],
+        );
+        let mut encoder_state = EncoderState::<30>::new(measurements[0]);
+        let start = encoder_state.unwrapped_position();
+        assert_eq!(start, -2 * 64);
+
+        encoder_state.update(measurements[1]);
+        assert_eq!(encoder_state.position(), SubStep::new(2 * 64));
+        assert_eq!(encoder_state.unwrapped_position(), start + 4 * 64);
+
+        // Moving clockwise the transition is at the top of the step.
+        encoder_state.update(measurements[2]);
+        assert_eq!(encoder_state.unwrapped_position(), start + 64);
+    }
+
+    #[test]
+    #[expect(clippy::float_cmp, reason = "Quarter turns are exactly representable")]
+    fn report_position_as_angle() {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Event::Mesurement),
+                (Instant::from_millis(10), Event::Step(-26)),
+                (Instant::from_millis(10), Event::Mesurement),
+            ],
+        );
+        let config = RotaryConfig::new(100);
+        let mut encoder_state = EncoderState::<30>::new(measurements[0]);
+        assert_eq!(encoder_state.angle(&config).degrees(), 0.0);
+        encoder_state.update(measurements[1]);
+        let angle = encoder_state.angle(&config);
+        assert_eq!(angle.revolution_count(), -1);
+        assert_eq!(angle.degrees(), 270.0);
+    }
+
+    #[test]
+    fn inverted_count_direction_mirrors_readings() {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Event::Mesurement),
+                // Power of two durations so the interpolation rounds the same way in both directions.
+                (Instant::from_micros(1 << 14), Event::Step(2)),
+                (Instant::from_micros(3 << 13), Event::Mesurement),
+            ],
+        );
+        let mut normal = EncoderState::<30>::new(measurements[0]);
+        let mut inverted =
+            EncoderState::<30>::with_count_direction(measurements[0], CountDirection::Inverted);
+        assert_eq!(inverted.position(), normal.position());
+        for measurement in &measurements[1..] {
+            normal.update(*measurement);
+            inverted.update(*measurement);
+        }
+        assert_eq!(inverted.steps(), Step::new(-3));
+        assert_eq!(
+            inverted.last_measurement().direction,
+            normal.last_measurement().direction.invert()
+        );
+        assert_eq!(inverted.speed().raw(), -normal.speed().raw());
+        assert_eq!(inverted.position().raw(), -normal.position().raw());
+        assert_eq!(inverted.unwrapped_position(), -normal.unwrapped_position());
+    }
+
+    #[test]
+    fn rejected_readings_keep_the_state() {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Event::Mesurement),
+                (Instant::from_millis(10), Event::Step(2)),
+                (Instant::from_millis(10), Event::Mesurement),
+                (Instant::from_millis(20), Event::Step(10)),
+                (Instant::from_millis(20), Event::Mesurement),
+            ],
+        );
+        let mut state = EncoderState::<30>::new(measurements[0]).with_max_step_jump(4);
+        assert_eq!(state.try_update(measurements[0]), Err(UpdateError::Stale));
+        assert_eq!(state.try_update(measurements[1]), Ok(()));
+        let before = state.clone();
+        assert_eq!(
+            state.try_update(measurements[2]),
+            Err(UpdateError::ImplausibleJump { steps: 8 })
+        );
+        assert_eq!(state.last_measurement(), before.last_measurement());
+        assert_eq!(state.unwrapped_position(), before.unwrapped_position());
+    }
+
+    #[test]
+    fn timer_wraps_are_undone() {
+        let reading = |direction, sample_s: u64, since_ms: u64| {
+            Measurement::new(
+                direction,
+                Step::new(5),
+                Instant::from_secs(sample_s),
+                Duration::from_millis(since_ms),
+            )
+        };
+        let mut state = EncoderState::<30>::new(reading(CounterClockwise, 10, 1000))
+            .with_timer_wrap(Duration::from_secs(100));
+        // Two wraps, the direction is back where it started.
+        state.update(reading(CounterClockwise, 259, 50_000));
+        assert_eq!(
+            state.last_measurement().time_since_transition(),
+            Duration::from_secs(250)
+        );
+        // A third wrap flips the direction.
+        state.update(reading(Clockwise, 319, 10_000));
+        assert_eq!(state.last_measurement().direction, CounterClockwise);
+        assert_eq!(
+            state.last_measurement().time_since_transition(),
+            Duration::from_secs(310)
+        );
+        assert_eq!(state.speed(), Speed::stopped());
+        // Moving to step 6 and back is not a wrap.
+        state.update(reading(Clockwise, 320, 500));
+        assert_eq!(state.last_measurement().direction, Clockwise);
+        assert_eq!(
+            state.last_measurement().time_since_transition(),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn round_trips_after_a_long_gap_are_not_wraps() {
+        let reading = |direction, sample_s: u64, since_s: u64| {
+            Measurement::new(
+                direction,
+                Step::new(5),
+                Instant::from_secs(sample_s),
+                Duration::from_secs(since_s),
+            )
+        };
+        let state = EncoderState::<30>::new(reading(CounterClockwise, 10, 1))
+            .with_timer_wrap(Duration::from_secs(100));
+        // A wrap 50 times over, 4 seconds off.
+        // The direction does not match an even number of wraps.
+        let mut even = state.clone();
+        even.update(reading(Clockwise, 5014, 1));
+        assert_eq!(even.last_measurement().direction, Clockwise);
+        assert_eq!(
+            even.last_measurement().time_since_transition(),
+            Duration::from_secs(1)
+        );
+        // A wrap 51 times over, 4 seconds off, is further off than a clock drift.
+        let mut odd = state.clone();
+        odd.update(reading(Clockwise, 5114, 1));
+        assert_eq!(
+            odd.last_measurement().time_since_transition(),
+            Duration::from_secs(1)
+        );
+        // A real wrap after the same gap is still undone.
+        let mut wrapped = state;
+        wrapped.update(reading(Clockwise, 5110, 1));
+        assert_eq!(
+            wrapped.last_measurement().time_since_transition(),
+            Duration::from_secs(5101)
+        );
+    }
 }
@@ -0,0 +1,365 @@
+use crate::{
+    Encoder, GearRatio, Speed, Step, SubStep, UpdateError, speed::SPEED_FRACTIONAL_BITS,
+    step::SUBSTEPS_PER_STEP,
+};
+
+const MICROS_PER_SECOND: i128 = 1_000_000;
+
+/// A linear distance stored as a whole number of micrometers.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Micrometers(i64);
+
+impl Micrometers {
+    pub const fn new(micrometers: i64) -> Self {
+        Self(micrometers)
+    }
+    /// # Panics
+    /// If the distance does not fit in an `i64` of micrometers.
+    pub const fn from_millimeters(millimeters: i64) -> Self {
+        match millimeters.checked_mul(1000) {
+            Some(micrometers) => Self(micrometers),
+            None => panic!("Distance out of range"),
+        }
+    }
+    pub const fn raw(&self) -> i64 {
+        self.0
+    }
+}
+
+/// A linear speed stored in micrometers per second.
+///
+/// Equivalent to mm/s with three fixed decimal places.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinearSpeed(i64);
+
+impl LinearSpeed {
+    pub const fn from_micrometers_per_second(micrometers_per_second: i64) -> Self {
+        Self(micrometers_per_second)
+    }
+    /// # Panics
+    /// If the speed does not fit in an `i64` of micrometers per second.
+    pub const fn from_millimeters_per_second(millimeters_per_second: i64) -> Self {
+        match millimeters_per_second.checked_mul(1000) {
+            Some(micrometers_per_second) => Self(micrometers_per_second),
+            None => panic!("Speed out of range"),
+        }
+    }
+    pub const fn micrometers_per_second(&self) -> i64 {
+        self.0
+    }
+}
+
+/// Scaling between encoder sub-steps and the travel of a linear axis.
+///
+/// The ratio between sub-steps and micrometers is kept as an exact fraction
+/// and positions are always converted from the absolute sub-step count,
+/// so there is no accumulated rounding error over long travels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinearConfig {
+    counts_per_revolution: u32,
+    travel_per_revolution: Micrometers,
+    gear_ratio: GearRatio,
+}
+
+impl LinearConfig {
+    /// `counts_per_revolution` is the number of [`Step`]s in one revolution of the encoder
+    /// and `travel_per_revolution` how far the axis moves for each revolution of the driven shaft.
+    ///
+    /// # Panics
+    /// If either value is zero.
+    pub const fn new(counts_per_revolution: u32, travel_per_revolution: Micrometers) -> Self {
+        assert!(counts_per_revolution != 0, "An encoder must have counts");
+        assert!(
+            travel_per_revolution.0 != 0,
+            "The axis must move when the shaft turns"
+        );
+        Self {
+            counts_per_revolution,
+            travel_per_revolution,
+            gear_ratio: GearRatio::direct(),
+        }
+    }
+    /// A lead screw that advances `lead` every revolution.
+    pub const fn lead_screw(counts_per_revolution: u32, lead: Micrometers) -> Self {
+        Self::new(counts_per_revolution, lead)
+    }
+    /// A toothed belt pulley, travel per revolution is `teeth * tooth_pitch`.
+    ///
+    /// # Panics
+    /// If either value is zero, or the travel does not fit in an `i64` of micrometers.
+    pub const fn pulley(counts_per_revolution: u32, teeth: u32, tooth_pitch: Micrometers) -> Self {
+        match (teeth as i64).checked_mul(tooth_pitch.0) {
+            Some(travel) => Self::new(counts_per_revolution, Micrometers(travel)),
+            None => panic!("Travel per revolution out of range"),
+        }
+    }
+    /// A measuring wheel.
+    ///
+    /// The circumference is taken directly (rather than the diameter) so that it can be
+    /// calibrated and to keep the scaling rational.
+    pub const fn wheel(counts_per_revolution: u32, circumference: Micrometers) -> Self {
+        Self::new(counts_per_revolution, circumference)
+    }
+    #[must_use]
+    pub const fn with_gear_ratio(mut self, gear_ratio: GearRatio) -> Self {
+        self.gear_ratio = gear_ratio;
+        self
+    }
+
+    /// Micrometers = sub-steps * numerator / denominator
+    fn numerator(&self) -> i128 {
+        i128::from(self.travel_per_revolution.0) * i128::from(self.gear_ratio.output_turns())
+    }
+    fn denominator(&self) -> i128 {
+        i128::from(self.counts_per_revolution)
+            * i128::from(SUBSTEPS_PER_STEP)
+            * i128::from(self.gear_ratio.encoder_turns())
+    }
+
+    /// Convert an unwrapped sub-step position into a distance (rounded towards negative infinity).
+    pub fn to_micrometers(&self, sub_steps: i64) -> Micrometers {
+        Micrometers(saturate(
+            (i128::from(sub_steps) * self.numerator()).div_euclid(self.denominator()),
+        ))
+    }
+    /// Convert a distance into the nearest unwrapped sub-step position. Used for set points.
+    pub fn to_sub_steps(&self, distance: Micrometers) -> i64 {
+        saturate(div_round(
+            i128::from(distance.0) * self.denominator(),
+            self.numerator(),
+        ))
+    }
+    /// Convert a distance into the encoder step it falls in.
+    pub fn to_step(&self, distance: Micrometers) -> Step {
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "Steps are intentionally wrapping"
+        )]
+        Step::new(
+            self.to_sub_steps(distance)
+                .div_euclid(SUBSTEPS_PER_STEP.into()) as i32,
+        )
+    }
+    pub fn to_linear_speed(&self, speed: Speed) -> LinearSpeed {
+        let numerator = i128::from(speed.raw()) * MICROS_PER_SECOND * self.numerator();
+        let denominator = self.denominator() << SPEED_FRACTIONAL_BITS;
+        LinearSpeed(saturate(div_round(numerator, denominator)))
+    }
+    /// Convert a linear speed into the encoder's native units. Used for set points.
+    pub fn to_speed(&self, speed: LinearSpeed) -> Speed {
+        let numerator = (i128::from(speed.0) * self.denominator()) << SPEED_FRACTIONAL_BITS;
+        let denominator = MICROS_PER_SECOND * self.numerator();
+        Speed::from_raw(saturate(div_round(numerator, denominator)))
+    }
+}
+
+/// Division rounding to the nearest integer (ties away from zero).
+fn div_round(numerator: i128, denominator: i128) -> i128 {
+    let (numerator, denominator) = if denominator < 0 {
+        (-numerator, -denominator)
+    } else {
+        (numerator, denominator)
+    };
+    let half = denominator / 2;
+    if numerator >= 0 {
+        (numerator + half) / denominator
+    } else {
+        (numerator - half) / denominator
+    }
+}
+
+fn saturate<T: TryFrom<i128> + Bounded>(value: i128) -> T {
+    T::try_from(value).unwrap_or(if value < 0 { T::MIN } else { T::MAX })
+}
+
+trait Bounded {
+    const MIN: Self;
+    const MAX: Self;
+}
+impl Bounded for i64 {
+    const MIN: Self = i64::MIN;
+    const MAX: Self = i64::MAX;
+}
+impl Bounded for i32 {
+    const MIN: Self = i32::MIN;
+    const MAX: Self = i32::MAX;
+}
+
+/// Wraps an [`Encoder`] and reports its position and speed along a linear axis.
+///
+/// The wrapper tracks the sub-step position across overflows,
+/// so `update` must be called at least once per 2^31 sub-steps of travel.
+pub struct LinearAxis<E: Encoder> {
+    encoder: E,
+    config: LinearConfig,
+    unwrapped_position: i64,
+}
+
+impl<E: Encoder> LinearAxis<E> {
+    /// The encoder's current position is used as the axis zero.
+    pub fn new(encoder: E, config: LinearConfig) -> Self {
+        Self::with_origin(encoder, config, Micrometers(0))
+    }
+    /// The encoder's current position is used as `origin`.
+    pub fn with_origin(encoder: E, config: LinearConfig, origin: Micrometers) -> Self {
+        Self {
+            encoder,
+            config,
+            unwrapped_position: config.to_sub_steps(origin),
+        }
+    }
+    pub fn config(&self) -> &LinearConfig {
+        &self.config
+    }
+    pub fn linear_position(&self) -> Micrometers {
+        self.config.to_micrometers(self.unwrapped_position)
+    }
+    pub fn linear_speed(&self) -> LinearSpeed {
+        self.config.to_linear_speed(self.encoder.speed())
+    }
+    /// Release the wrapped encoder.
+    pub fn into_inner(self) -> E {
+        self.encoder
+    }
+}
+
+impl<E: Encoder> LinearAxis<E> {
+    fn track(&mut self, prev_position: SubStep) {
+        self.unwrapped_position += i64::from((self.encoder.position() - prev_position).raw());
+    }
+}
+
+impl<E: Encoder> Encoder for LinearAxis<E> {
+    fn update(&mut self) {
+        let prev_position = self.encoder.position();
+        self.encoder.update();
+        self.track(prev_position);
+    }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        let prev_position = self.encoder.position();
+        let result = self.encoder.try_update();
+        self.track(prev_position);
+        result
+    }
+    fn speed(&self) -> Speed {
+        self.encoder.speed()
+    }
+    fn position(&self) -> SubStep {
+        self.encoder.position()
+    }
+    fn ticks(&self) -> Step {
+        self.encoder.ticks()
+    }
+    fn error_count(&self) -> Option<u32> {
+        self.encoder.error_count()
+    }
+    fn sample_instant(&self) -> Option<embassy_time::Instant> {
+        self.encoder.sample_instant()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinearAxis, LinearConfig, LinearSpeed, Micrometers};
+    use crate::{Encoder, GearRatio, Speed, Step, SubStep, UpdateError, mock::ScriptedEncoder};
+    use embassy_time::Duration;
+
+    /// 1000 counts per revolution with a 5mm lead.
+    const SCREW: LinearConfig = LinearConfig::lead_screw(1000, Micrometers::from_millimeters(5));
+
+    #[test]
+    fn lead_screw() {
+        // 64_000 sub-steps per 5mm
+        assert_eq!(SCREW.to_micrometers(64_000), Micrometers::new(5_000));
+        assert_eq!(SCREW.to_micrometers(-64_000), Micrometers::new(-5_000));
+        assert_eq!(SCREW.to_micrometers(64), Micrometers::new(5));
+        assert_eq!(SCREW.to_sub_steps(Micrometers::new(5)), 64);
+        assert_eq!(SCREW.to_step(Micrometers::new(5_000)), Step::new(1000));
+    }
+
+    #[test]
+    fn pulley_with_gearing() {
+        // GT2 belt, 20 teeth, 2mm pitch, 3:1 reduction between motor and pulley.
+        let config = LinearConfig::pulley(400, 20, Micrometers::new(2_000))
+            .with_gear_ratio(GearRatio::new(3, 1));
+        let sub_steps_per_pulley_revolution = 400 * 64 * 3;
+        assert_eq!(
+            config.to_micrometers(sub_steps_per_pulley_revolution),
+            Micrometers::new(40_000)
+        );
+    }
+
+    #[test]
+    fn no_drift_over_long_travels() {
+        // 7 counts per 3µm never divides evenly.
+        let config = LinearConfig::wheel(7, Micrometers::new(3));
+        for revolutions in [1, 1_000, 1_000_000, 1_000_000_000] {
+            assert_eq!(
+                config.to_micrometers(revolutions * 7 * 64),
+                Micrometers::new(revolutions * 3)
+            );
+        }
+        // Round tripping set points is exact at whole revolutions.
+        let set_point = Micrometers::new(3_000_000_000);
+        assert_eq!(
+            config.to_micrometers(config.to_sub_steps(set_point)),
+            set_point
+        );
+    }
+
+    #[test]
+    fn speed_conversion() {
+        // one revolution per second
+        let speed = Speed::new(SubStep::new(64_000), Duration::from_secs(1));
+        assert_eq!(
+            SCREW.to_linear_speed(speed),
+            LinearSpeed::from_millimeters_per_second(5)
+        );
+        let speed = Speed::new(SubStep::new(-64_000), Duration::from_secs(1));
+        assert_eq!(
+            SCREW.to_linear_speed(speed),
+            LinearSpeed::from_millimeters_per_second(-5)
+        );
+        let set_point = LinearSpeed::from_millimeters_per_second(5);
+        assert_eq!(SCREW.to_linear_speed(SCREW.to_speed(set_point)), set_point);
+    }
+
+    #[test]
+    #[should_panic(expected = "Distance out of range")]
+    fn millimeters_out_of_range() {
+        let _ = Micrometers::from_millimeters(i64::MAX / 100);
+    }
+
+    #[test]
+    fn axis_tracks_past_sub_step_overflow() {
+        let config = LinearConfig::lead_screw(1 << 24, Micrometers::new(1 << 30));
+        let quarter = 1 << 30;
+        let positions = [0, quarter, i32::MIN, -quarter].repeat(3);
+        let mut axis = LinearAxis::with_origin(
+            ScriptedEncoder::new(&positions[..9]),
+            config,
+            Micrometers::new(10),
+        );
+        for _ in 0..8 {
+            axis.update();
+        }
+        // 8 * 2^30 sub-steps = 8 revolutions, which overflows the sub-step counter twice.
+        assert_eq!(axis.linear_position(), Micrometers::new(10 + (8 << 30)));
+    }
+
+    #[test]
+    fn inner_errors_are_reported() {
+        let failures = [None, Some(UpdateError::FifoTimeout), None];
+        let encoder = ScriptedEncoder::new(&[0, 64_000, 128_000]).with_failures(&failures);
+        let mut axis = LinearAxis::new(encoder, SCREW);
+        assert_eq!(axis.try_update(), Ok(()));
+        assert_eq!(axis.try_update(), Err(UpdateError::FifoTimeout));
+        // The failed update did not move the axis.
+        assert_eq!(axis.linear_position(), Micrometers::new(5_000));
+        assert_eq!(axis.try_update(), Ok(()));
+        assert_eq!(axis.linear_position(), Micrometers::new(10_000));
+    }
+}
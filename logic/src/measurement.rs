@@ -1,7 +1,7 @@
 use core::ops::Range;
 
 use crate::{
-    CalibrationData, Direction,
+    CalibrationData, DESYNC_TOLERANCE, Direction,
     speed::Speed,
     step::{Step, SubStep},
 };
@@ -19,6 +19,10 @@ pub struct Measurement {
     pub step_instant: embassy_time::Instant,
     /// The time when this measurement was read from the pio.
     pub sample_instant: embassy_time::Instant,
+    /// How many nanoseconds before `step_instant` the step was registered, always below 1000.
+    ///
+    /// [`Instant`] only counts whole microseconds, but the PIO times steps to a few cycles.
+    pub step_fraction_ns: u16,
 }
 
 impl Measurement {
@@ -33,6 +37,29 @@ impl Measurement {
             direction,
             step_instant: sample_instant - time_since_transition,
             sample_instant,
+            step_fraction_ns: 0,
+        }
+    }
+    /// Same as [`Self::new`] with the time since the transition in nanoseconds.
+    ///
+    /// # Panics
+    /// Never, the fraction is always below 1000.
+    pub fn from_nanos(
+        direction: Direction,
+        steps: Step,
+        sample_instant: Instant,
+        nanos_since_transition: u64,
+    ) -> Self {
+        let micros = nanos_since_transition / 1000;
+        let fraction = nanos_since_transition % 1000;
+        Self {
+            step_fraction_ns: u16::try_from(fraction).expect("The remainder is below 1000"),
+            ..Self::new(
+                direction,
+                steps,
+                sample_instant,
+                Duration::from_micros(micros),
+            )
         }
     }
     /// The subset where the most recent step step occurred.
@@ -47,6 +74,55 @@ impl Measurement {
     pub fn time_since_transition(&self) -> Duration {
         self.sample_instant - self.step_instant
     }
+    /// Same as [`Self::time_since_transition`] including the sub-microsecond part.
+    pub fn nanos_since_transition(&self) -> u64 {
+        self.time_since_transition().as_micros() * 1000 + u64::from(self.step_fraction_ns)
+    }
+    /// Undo wraps of the PIO transition timer, which restarts every `wrap_period`.
+    ///
+    /// A wrap looks like a transition back onto the same step, with the timer restarted and the
+    /// direction flipped. It is told apart from real motion by landing a whole number of wrap
+    /// periods after the `previous` transition, with the direction flipped once per wrap.
+    /// The returned reading keeps the previous transition, so the time since it is correct for
+    /// any idle period.
+    ///
+    /// A real round trip that happens to match both checks is still mistaken for a wrap.
+    #[must_use]
+    pub fn extend_across_wraps(self, previous: &Measurement, wrap_period: Duration) -> Self {
+        if self.step != previous.step
+            || self.step_instant <= previous.step_instant + DESYNC_TOLERANCE
+            || wrap_period.as_ticks() == 0
+        {
+            return self;
+        }
+        let since_previous = self
+            .sample_instant
+            .saturating_duration_since(previous.step_instant);
+        let Some(missing) = since_previous.checked_sub(self.time_since_transition()) else {
+            return self;
+        };
+        let period = wrap_period.as_micros();
+        let wraps = (missing.as_micros() + period / 2) / period;
+        let error = missing.as_micros().abs_diff(wraps * period);
+        // Allow for the PIO and embassy clocks disagreeing by up to 0.1%,
+        // capped so a long gap does not leave a wide window for real motion to land in.
+        let drift = (missing.as_micros() / 1024).min(period / 64);
+        let tolerance = DESYNC_TOLERANCE.as_micros() + drift;
+        let flipped = self.direction != previous.direction;
+        if wraps == 0 || error > tolerance || flipped != (wraps % 2 == 1) {
+            return self;
+        }
+        Self {
+            direction: previous.direction,
+            step_instant: previous.step_instant,
+            step_fraction_ns: previous.step_fraction_ns,
+            ..self
+        }
+    }
+    /// When the step was registered, in nanoseconds since boot.
+    fn step_nanos(&self) -> u64 {
+        (self.step_instant.as_micros() * 1000).saturating_sub(self.step_fraction_ns.into())
+    }
 }
 
 impl Measurement {
@@ -65,9 +141,9 @@ impl Measurement {
             //No new transitions have occurred, we cannot provide an updated speed estimate
             None
         } else {
-            Some(Speed::new(
+            Some(Speed::from_nanos(
                 current.transition(calibration_data) - previous.transition(calibration_data),
-                current.step_instant - previous.step_instant,
+                current.step_nanos() - previous.step_nanos(),
             ))
         }
     }
@@ -80,8 +156,9 @@ impl Measurement {
     ) -> Range<Speed> {
         let transition_point = current.transition(cali);
         // Insure duration is always positive.
-        let delta_prev_to_t = duration_dif_abs(previous.sample_instant, current.step_instant);
-        let delta_t_to_current = current.time_since_transition();
+        let delta_prev_to_t =
+            (previous.sample_instant.as_micros() * 1000).abs_diff(current.step_nanos());
+        let delta_t_to_current = current.nanos_since_transition();
 
         // We want to always use the largest time delta possible.
         // There are a couple of scenarios that could be happening.
@@ -94,12 +171,12 @@ impl Measurement {
             let range = previous.step.substep_range(cali);
             //NOTE: this is (initial - final) rather than (final-initial) to compensate for the fact
             //that embassy doesn't support negative durations.
-            Speed::new(transition_point - range.end, delta_prev_to_t)
-                ..Speed::new(transition_point - range.start, delta_prev_to_t)
+            Speed::from_nanos(transition_point - range.end, delta_prev_to_t)
+                ..Speed::from_nanos(transition_point - range.start, delta_prev_to_t)
         } else {
             let range = current.step.substep_range(cali);
-            Speed::new(range.start - transition_point, delta_t_to_current)
-                ..Speed::new(range.end - transition_point, delta_t_to_current)
+            Speed::from_nanos(range.start - transition_point, delta_t_to_current)
+                ..Speed::from_nanos(range.end - transition_point, delta_t_to_current)
         }
     }
     pub fn estimate_speed(
@@ -115,14 +192,6 @@ impl Measurement {
     }
 }
 
-/// Get the absolute value of the duration between two instances.
-#[mutants::skip]
-fn duration_dif_abs(
-    t_0: embassy_time::Instant,
-    t_1: embassy_time::Instant,
-) -> embassy_time::Duration {
-    if t_0 > t_1 { t_0 - t_1 } else { t_1 - t_0 }
-}
 #[cfg(test)]
 pub mod tests {
     use crate::EQUAL_STEPS;
@@ -162,6 +231,7 @@ pub mod tests {
                 direction: self.direction_of_travel,
                 step_instant: self.step_instant,
                 sample_instant: now,
+                step_fraction_ns: 0,
             }
         }
     }
@@ -208,11 +278,30 @@ pub mod tests {
                 step: Step::new(42),
                 direction: Direction::CounterClockwise,
                 step_instant: time - Duration::from_micros(65),
-                sample_instant: time
+                sample_instant: time,
+                step_fraction_ns: 0,
             }
         );
     }
 
+    #[test]
+    fn sub_microsecond_step_times() {
+        let sample = Instant::from_millis(1);
+        let measurement =
+            Measurement::from_nanos(Direction::CounterClockwise, Step::new(1), sample, 2_300);
+        assert_eq!(measurement.step_instant, sample - Duration::from_micros(2));
+        assert_eq!(measurement.step_fraction_ns, 300);
+        assert_eq!(measurement.nanos_since_transition(), 2_300);
+
+        // Two steps 1.5us apart, at 1us resolution this would read as 1us or 2us.
+        let previous =
+            Measurement::from_nanos(Direction::CounterClockwise, Step::new(0), sample, 3_800);
+        assert_eq!(
+            Measurement::calculate_speed(previous, measurement, &EQUAL_STEPS),
+            Some(Speed::from_nanos(SubStep::new(64), 1_500))
+        );
+    }
+
     #[test]
     fn always_use_larger_delta_time_for_estiments() {
         let step_time = Instant::from_millis(30);
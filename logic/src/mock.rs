@@ -0,0 +1,428 @@
+//! A simulated encoder for testing application code on the host.
+//!
+//! A [`Trajectory`] describes how the encoder moves over time, a [`MockEncoder`] plays it back
+//! on a virtual clock and feeds the resulting readings through the real [`EncoderState`],
+//! so speed and position estimates behave like they would on hardware.
+//!
+//! ```rust
+//! use embassy_time::{Duration, Instant};
+//! use pio_speed_encoder_logic::Encoder;
+//! use pio_speed_encoder_logic::mock::{MockEncoder, Segment, Trajectory};
+//!
+//! let segments = [
+//!     Segment::ramp(0, 1000, Duration::from_millis(100)),
+//!     Segment::constant(1000, Duration::from_millis(100)),
+//!     Segment::ramp(1000, 0, Duration::from_millis(100)),
+//! ];
+//! let mut encoder = MockEncoder::<30>::new(Trajectory::new(&segments), Instant::from_secs(0));
+//! for _ in 0..30 {
+//!     encoder.advance(Duration::from_millis(10));
+//!     encoder.update();
+//! }
+//! assert_eq!(encoder.ticks(), encoder.true_step());
+//! ```
+use embassy_time::{Duration, Instant};
+
+use crate::{Direction, Encoder, EncoderState, Measurement, Speed, Step, SubStep, UpdateError};
+
+/// Motion is tracked in millionths of a step, so one step per second moves one unit per micro second.
+const MICRO_STEPS_PER_STEP: i64 = 1_000_000;
+
+/// One piece of a [`Trajectory`], speeds are in steps per second.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Segment {
+    /// Move at a constant speed, zero to stand still.
+    Constant { speed: i32, duration: Duration },
+    /// Change speed linearly, ramping through zero reverses the encoder.
+    Ramp {
+        from: i32,
+        to: i32,
+        duration: Duration,
+    },
+}
+
+impl Segment {
+    pub fn constant(speed: i32, duration: Duration) -> Self {
+        Segment::Constant { speed, duration }
+    }
+    pub fn ramp(from: i32, to: i32, duration: Duration) -> Self {
+        Segment::Ramp { from, to, duration }
+    }
+    /// Stand still.
+    pub fn hold(duration: Duration) -> Self {
+        Segment::constant(0, duration)
+    }
+    /// Decelerate from `speed` to a stop.
+    pub fn stop(speed: i32, duration: Duration) -> Self {
+        Segment::ramp(speed, 0, duration)
+    }
+    /// Ramp from `speed` to the same speed in the other direction.
+    pub fn reverse(speed: i32, duration: Duration) -> Self {
+        Segment::ramp(speed, -speed, duration)
+    }
+    pub fn duration(&self) -> Duration {
+        match *self {
+            Segment::Constant { duration, .. } | Segment::Ramp { duration, .. } => duration,
+        }
+    }
+    /// Distance traveled `elapsed` into the segment, in micro steps.
+    fn displacement(&self, elapsed: Duration) -> i128 {
+        let t = i128::from(elapsed.as_micros());
+        match *self {
+            Segment::Constant { speed, .. } => i128::from(speed) * t,
+            Segment::Ramp { from, to, duration } => {
+                let length = i128::from(duration.as_micros()).max(1);
+                i128::from(from) * t + (i128::from(to) - i128::from(from)) * t * t / (2 * length)
+            }
+        }
+    }
+    fn speed(&self, elapsed: Duration) -> i32 {
+        match *self {
+            Segment::Constant { speed, .. } => speed,
+            Segment::Ramp { from, to, duration } => {
+                let length = i128::from(duration.as_micros()).max(1);
+                let t = i128::from(elapsed.as_micros());
+                let speed = i128::from(from) + (i128::from(to) - i128::from(from)) * t / length;
+                i32::try_from(speed).unwrap_or(to)
+            }
+        }
+    }
+}
+
+/// A sequence of segments played back one after the other.
+///
+/// The encoder holds its final position once the last segment is done.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Trajectory<'a> {
+    segments: &'a [Segment],
+}
+
+impl<'a> Trajectory<'a> {
+    pub fn new(segments: &'a [Segment]) -> Self {
+        Self { segments }
+    }
+    pub fn duration(&self) -> Duration {
+        self.segments
+            .iter()
+            .fold(Duration::from_ticks(0), |total, segment| {
+                total + segment.duration()
+            })
+    }
+    /// Find the segment active `elapsed` after the start, the position it starts at
+    /// and how far into it `elapsed` is.
+    fn locate(&self, mut elapsed: Duration) -> Option<(&Segment, i128, Duration)> {
+        let mut start = 0;
+        for segment in self.segments {
+            if elapsed < segment.duration() {
+                return Some((segment, start, elapsed));
+            }
+            start += segment.displacement(segment.duration());
+            elapsed -= segment.duration();
+        }
+        None
+    }
+    fn micro_steps(&self, elapsed: Duration) -> i128 {
+        match self.locate(elapsed) {
+            Some((segment, start, into)) => start + segment.displacement(into),
+            None => self
+                .segments
+                .iter()
+                .map(|segment| segment.displacement(segment.duration()))
+                .sum(),
+        }
+    }
+    /// Speed in steps per second, `elapsed` after the start.
+    pub fn speed(&self, elapsed: Duration) -> i32 {
+        self.locate(elapsed)
+            .map_or(0, |(segment, _, into)| segment.speed(into))
+    }
+    /// Exact step `elapsed` after the start.
+    pub fn step(&self, elapsed: Duration) -> Step {
+        let step = self
+            .micro_steps(elapsed)
+            .div_euclid(i128::from(MICRO_STEPS_PER_STEP));
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "steps wrap the same way the PIO counter does"
+        )]
+        Step::new(step as i32)
+    }
+    /// Exact position in sub-steps `elapsed` after the start, assuming equally sized steps.
+    pub fn position(&self, elapsed: Duration) -> SubStep {
+        let sub_steps = self.micro_steps(elapsed) * i128::from(crate::step::SUBSTEPS_PER_STEP)
+            / i128::from(MICRO_STEPS_PER_STEP);
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "sub-steps wrap the same way the PIO counter does"
+        )]
+        SubStep::new(sub_steps as i32)
+    }
+}
+
+/// An [`Encoder`] that follows a [`Trajectory`] on a virtual clock.
+///
+/// Call [`Self::advance`] to move the clock, then [`Encoder::update`] to take a reading.
+pub struct MockEncoder<'a, const IDLE_STOPING_TIME_MS: u64> {
+    trajectory: Trajectory<'a>,
+    start: Instant,
+    now: Instant,
+    resolution: Duration,
+    step: Step,
+    direction: Direction,
+    step_instant: Instant,
+    state: EncoderState<IDLE_STOPING_TIME_MS>,
+}
+
+impl<'a, const IDLE_STOPING_TIME_MS: u64> MockEncoder<'a, IDLE_STOPING_TIME_MS> {
+    /// Start playing `trajectory` at `start` on the virtual clock.
+    pub fn new(trajectory: Trajectory<'a>, start: Instant) -> Self {
+        let step = trajectory.step(Duration::from_ticks(0));
+        let direction = Direction::CounterClockwise;
+        Self {
+            trajectory,
+            start,
+            now: start,
+            resolution: Duration::from_micros(10),
+            step,
+            direction,
+            step_instant: start,
+            state: EncoderState::new(Measurement {
+                step,
+                direction,
+                step_instant: start,
+                sample_instant: start,
+                step_fraction_ns: 0,
+            }),
+        }
+    }
+    /// How precisely step times are simulated, defaults to 10us.
+    ///
+    /// Finer resolutions are more accurate but take longer to [`Self::advance`].
+    #[must_use]
+    pub fn with_resolution(mut self, resolution: Duration) -> Self {
+        self.resolution = resolution;
+        self
+    }
+    /// Move the virtual clock forward, recording every step along the way.
+    pub fn advance(&mut self, duration: Duration) {
+        let end = self.now + duration;
+        while self.now < end {
+            self.now = (self.now + self.resolution).min(end);
+            let step = self.trajectory.step(self.now - self.start);
+            if step != self.step
+                && let Some(direction) = self.step.comp(step)
+            {
+                self.direction = direction;
+                self.step = step;
+                self.step_instant = self.now;
+            }
+        }
+    }
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+    /// What the PIO would report at the current time.
+    pub fn measurement(&self) -> Measurement {
+        Measurement {
+            step: self.step,
+            direction: self.direction,
+            step_instant: self.step_instant,
+            sample_instant: self.now,
+            step_fraction_ns: 0,
+        }
+    }
+    /// The estimator fed with the simulated readings.
+    pub fn state(&self) -> &EncoderState<IDLE_STOPING_TIME_MS> {
+        &self.state
+    }
+    /// The step the trajectory is on, without any estimation.
+    pub fn true_step(&self) -> Step {
+        self.step
+    }
+    /// The exact position on the trajectory, to compare estimates against.
+    pub fn true_position(&self) -> SubStep {
+        self.trajectory.position(self.now - self.start)
+    }
+    /// The exact speed on the trajectory in steps per second.
+    pub fn true_speed(&self) -> i32 {
+        self.trajectory.speed(self.now - self.start)
+    }
+}
+
+impl<const IDLE_STOPING_TIME_MS: u64> Encoder for MockEncoder<'_, IDLE_STOPING_TIME_MS> {
+    /// Does nothing unless the clock has advanced since the last update.
+    fn update(&mut self) {
+        if self.now > self.state.last_measurement().sample_instant {
+            self.state.update(self.measurement());
+        }
+    }
+    fn speed(&self) -> Speed {
+        self.state.speed()
+    }
+    fn position(&self) -> SubStep {
+        self.state.position()
+    }
+    fn ticks(&self) -> Step {
+        self.state.steps()
+    }
+    fn sample_instant(&self) -> Option<Instant> {
+        Some(self.state.last_measurement().sample_instant)
+    }
+}
+
+/// An [`Encoder`] that plays back exact readings, one per [`Encoder::update`].
+///
+/// Useful when a test needs precise positions rather than a simulated motion.
+/// The last reading is held once the script runs out.
+pub struct ScriptedEncoder<'a> {
+    positions: &'a [i32],
+    speeds: &'a [Speed],
+    error_counts: &'a [u32],
+    instants: &'a [Instant],
+    failures: &'a [Option<UpdateError>],
+    attempts: usize,
+    index: usize,
+}
+
+impl<'a> ScriptedEncoder<'a> {
+    /// Sub-step positions, the speed is stopped unless [`Self::with_speeds`] is used.
+    ///
+    /// # Panics
+    /// If `positions` is empty.
+    pub fn new(positions: &'a [i32]) -> Self {
+        assert!(
+            !positions.is_empty(),
+            "The script needs at least one reading"
+        );
+        Self {
+            positions,
+            speeds: &[],
+            error_counts: &[],
+            instants: &[],
+            failures: &[],
+            attempts: 0,
+            index: 0,
+        }
+    }
+    /// Speeds to report alongside the positions.
+    #[must_use]
+    pub fn with_speeds(mut self, speeds: &'a [Speed]) -> Self {
+        self.speeds = speeds;
+        self
+    }
+    /// Values for [`Encoder::error_count`], which is `None` without them.
+    #[must_use]
+    pub fn with_error_counts(mut self, error_counts: &'a [u32]) -> Self {
+        self.error_counts = error_counts;
+        self
+    }
+    /// Values for [`Encoder::sample_instant`], which is `None` without them.
+    #[must_use]
+    pub fn with_instants(mut self, instants: &'a [Instant]) -> Self {
+        self.instants = instants;
+        self
+    }
+    /// Outcomes of successive [`Encoder::try_update`] calls, a failed update keeps the reading.
+    #[must_use]
+    pub fn with_failures(mut self, failures: &'a [Option<UpdateError>]) -> Self {
+        self.failures = failures;
+        self
+    }
+    fn reading<T: Copy>(&self, script: &[T]) -> Option<T> {
+        script.get(self.index).or(script.last()).copied()
+    }
+}
+
+impl Encoder for ScriptedEncoder<'_> {
+    fn update(&mut self) {
+        self.index += 1;
+    }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        let failure = self.failures.get(self.attempts).copied().flatten();
+        self.attempts += 1;
+        if let Some(error) = failure {
+            return Err(error);
+        }
+        self.update();
+        Ok(())
+    }
+    fn speed(&self) -> Speed {
+        self.reading(self.speeds).unwrap_or(Speed::stopped())
+    }
+    fn position(&self) -> SubStep {
+        SubStep::new(self.reading(self.positions).unwrap_or_default())
+    }
+    fn ticks(&self) -> Step {
+        Step::new(self.reading(self.positions).unwrap_or_default() >> 6)
+    }
+    fn error_count(&self) -> Option<u32> {
+        self.reading(self.error_counts)
+    }
+    fn sample_instant(&self) -> Option<Instant> {
+        self.reading(self.instants)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MockEncoder, ScriptedEncoder, Segment, Trajectory};
+    use crate::{Direction, Encoder, Speed, Step, SubStep};
+    use embassy_time::{Duration, Instant};
+
+    fn run<const IDLE: u64>(encoder: &mut MockEncoder<'_, IDLE>, updates: u32) {
+        for _ in 0..updates {
+            encoder.advance(Duration::from_millis(10));
+            encoder.update();
+        }
+    }
+
+    #[test]
+    fn constant_speed() {
+        let segments = [Segment::constant(1000, Duration::from_secs(1))];
+        let mut encoder = MockEncoder::<30>::new(Trajectory::new(&segments), Instant::from_secs(5));
+        run(&mut encoder, 10);
+        assert_eq!(encoder.ticks(), Step::new(100));
+        assert_eq!(encoder.true_position(), SubStep::new(6400));
+        // 1000 steps per second.
+        let expected = Speed::new(SubStep::new(64_000), Duration::from_secs(1));
+        assert!((encoder.speed().raw() - expected.raw()).abs() < expected.raw() / 100);
+    }
+
+    #[test]
+    fn reversal_and_stop() {
+        let segments = [
+            Segment::constant(500, Duration::from_millis(100)),
+            Segment::reverse(500, Duration::from_millis(100)),
+            Segment::stop(-500, Duration::from_millis(100)),
+        ];
+        let trajectory = Trajectory::new(&segments);
+        assert_eq!(trajectory.duration(), Duration::from_millis(300));
+        assert_eq!(trajectory.step(Duration::from_millis(100)), Step::new(50));
+        // The reversal is symmetric.
+        assert_eq!(trajectory.step(Duration::from_millis(200)), Step::new(50));
+        assert_eq!(trajectory.step(Duration::from_millis(300)), Step::new(25));
+
+        let mut encoder = MockEncoder::<30>::new(trajectory, Instant::from_secs(0));
+        run(&mut encoder, 17);
+        assert_eq!(encoder.measurement().direction, Direction::Clockwise);
+        assert!(encoder.speed().raw() < 0);
+        run(&mut encoder, 20);
+        assert_eq!(encoder.ticks(), Step::new(25));
+        assert_eq!(encoder.true_speed(), 0);
+        assert_eq!(encoder.speed(), Speed::stopped());
+    }
+
+    #[test]
+    fn scripted_readings() {
+        let speeds = [Speed::stopped(), Speed::from_raw(10)];
+        let mut encoder = ScriptedEncoder::new(&[0, -70]).with_speeds(&speeds);
+        assert_eq!(encoder.error_count(), None);
+        encoder.update();
+        assert_eq!(encoder.position(), SubStep::new(-70));
+        assert_eq!(encoder.ticks(), Step::new(-2));
+        assert_eq!(encoder.speed(), Speed::from_raw(10));
+        // The last reading is held.
+        encoder.update();
+        assert_eq!(encoder.position(), SubStep::new(-70));
+    }
+}
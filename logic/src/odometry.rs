@@ -0,0 +1,292 @@
+use core::f32::consts::TAU;
+
+use embassy_time::Instant;
+
+use crate::{Encoder, SubStep, step::SUBSTEPS_PER_STEP};
+
+/// Turns below this many radians per update are integrated as straight lines,
+/// to avoid dividing by (nearly) zero.
+const STRAIGHT_LINE_THRESHOLD: f32 = 1e-6;
+
+/// Geometry of a differential drive robot, lengths are in meters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OdometryConfig {
+    counts_per_revolution: u32,
+    wheel_radius: f32,
+    track_width: f32,
+}
+
+impl OdometryConfig {
+    /// `counts_per_revolution` is the number of [`Step`](crate::Step)s in one revolution of a wheel,
+    /// `track_width` is the distance between the wheel contact points.
+    ///
+    /// # Panics
+    /// If any value is zero.
+    pub const fn new(counts_per_revolution: u32, wheel_radius: f32, track_width: f32) -> Self {
+        assert!(counts_per_revolution != 0, "An encoder must have counts");
+        assert!(
+            wheel_radius != 0.0 && track_width != 0.0,
+            "The robot must have a size"
+        );
+        Self {
+            counts_per_revolution,
+            wheel_radius,
+            track_width,
+        }
+    }
+    pub const fn track_width(&self) -> f32 {
+        self.track_width
+    }
+    /// Distance a wheel rolls for a change in sub-steps.
+    pub fn to_meters(&self, sub_steps: i32) -> f32 {
+        #[expect(
+            clippy::cast_precision_loss,
+            reason = "f32 has plenty of precision for the change between two updates"
+        )]
+        let revolutions = sub_steps as f32
+            / (u64::from(self.counts_per_revolution) * u64::from(SUBSTEPS_PER_STEP)) as f32;
+        revolutions * TAU * self.wheel_radius
+    }
+}
+
+/// Position and heading in the plane, `theta` is in radians in `[-π, π]`, counterclockwise from the x axis.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+}
+
+/// Body velocity, `linear` in meters per second along the heading and `angular` in radians per second.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Twist {
+    pub linear: f32,
+    pub angular: f32,
+}
+
+/// Integrates the pose of a differential drive robot from its wheel encoders.
+///
+/// Both encoders must count up when their wheel drives the robot forward,
+/// use [`EncoderExt::inverted`](crate::EncoderExt::inverted) on the mirrored side if required.
+/// Between updates each wheel is assumed to move at a constant speed, so the robot follows
+/// an arc which is integrated exactly.
+///
+/// Wheel speeds are timed with each encoder's own [`Encoder::sample_instant`],
+/// so encoders sampled at slightly different times still give the right [`Twist`].
+pub struct DifferentialDrive<L: Encoder, R: Encoder> {
+    left: Wheel<L>,
+    right: Wheel<R>,
+    config: OdometryConfig,
+    pose: Pose,
+}
+
+/// An encoder and the reading it was at on the previous update.
+struct Wheel<E: Encoder> {
+    encoder: E,
+    position: SubStep,
+    sampled: Instant,
+    /// Meters per second, between the last two updates.
+    speed: f32,
+}
+
+impl<E: Encoder> Wheel<E> {
+    fn new(encoder: E, now: Instant) -> Self {
+        Self {
+            position: encoder.position(),
+            sampled: encoder.sample_instant().unwrap_or(now),
+            encoder,
+            speed: 0.0,
+        }
+    }
+    /// Update the encoder and return how far the wheel rolled, in meters.
+    fn update(&mut self, config: &OdometryConfig, now: Instant) -> f32 {
+        self.encoder.update();
+        let distance = config.to_meters((self.encoder.position() - self.position).raw());
+        self.position = self.encoder.position();
+        let sampled = self.encoder.sample_instant().unwrap_or(now);
+        let elapsed = sampled.saturating_duration_since(self.sampled);
+        self.sampled = sampled;
+        if elapsed.as_micros() > 0 {
+            #[expect(
+                clippy::cast_precision_loss,
+                reason = "update intervals are far shorter than f32's exact range"
+            )]
+            let seconds = elapsed.as_micros() as f32 / 1e6;
+            self.speed = distance / seconds;
+        }
+        distance
+    }
+}
+
+impl<L: Encoder, R: Encoder> DifferentialDrive<L, R> {
+    /// Start at `pose`, `now` is when the encoders were last updated.
+    ///
+    /// `now` is only used for encoders without an [`Encoder::sample_instant`].
+    pub fn new(left: L, right: R, config: OdometryConfig, pose: Pose, now: Instant) -> Self {
+        Self {
+            left: Wheel::new(left, now),
+            right: Wheel::new(right, now),
+            config,
+            pose,
+        }
+    }
+    pub fn config(&self) -> &OdometryConfig {
+        &self.config
+    }
+    pub fn left(&self) -> &L {
+        &self.left.encoder
+    }
+    pub fn right(&self) -> &R {
+        &self.right.encoder
+    }
+    /// Release the wrapped encoders.
+    pub fn into_inner(self) -> (L, R) {
+        (self.left.encoder, self.right.encoder)
+    }
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+    /// Overwrite the pose, e.g. after a correction from another sensor.
+    pub fn set_pose(&mut self, pose: Pose) {
+        self.pose = pose;
+    }
+    /// Average velocity between the last two updates.
+    pub fn twist(&self) -> Twist {
+        Twist {
+            linear: f32::midpoint(self.left.speed, self.right.speed),
+            angular: (self.right.speed - self.left.speed) / self.config.track_width,
+        }
+    }
+
+    /// Update both encoders and integrate the motion since the previous update.
+    ///
+    /// `now` stands in for the sample time of encoders without an [`Encoder::sample_instant`],
+    /// it should be when both were sampled.
+    pub fn update(&mut self, now: Instant) {
+        let left = self.left.update(&self.config, now);
+        let right = self.right.update(&self.config, now);
+
+        let distance = f32::midpoint(left, right);
+        let turn = (right - left) / self.config.track_width;
+        self.pose = integrate(self.pose, distance, turn);
+    }
+}
+
+/// Move `distance` along an arc that turns by `turn` radians.
+fn integrate(pose: Pose, distance: f32, turn: f32) -> Pose {
+    let theta = pose.theta + turn;
+    let (dx, dy) = if turn.abs() < STRAIGHT_LINE_THRESHOLD {
+        let heading = pose.theta + turn / 2.0;
+        (
+            distance * libm::cosf(heading),
+            distance * libm::sinf(heading),
+        )
+    } else {
+        let radius = distance / turn;
+        (
+            radius * (libm::sinf(theta) - libm::sinf(pose.theta)),
+            -radius * (libm::cosf(theta) - libm::cosf(pose.theta)),
+        )
+    };
+    Pose {
+        x: pose.x + dx,
+        y: pose.y + dy,
+        theta: libm::remainderf(theta, TAU),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DifferentialDrive, OdometryConfig, Pose};
+    use crate::mock::ScriptedEncoder;
+    use core::f32::consts::{FRAC_PI_2, PI, TAU};
+    use embassy_time::Instant;
+
+    /// One sub-step rolls 1mm, a spin on the spot rolls each wheel 0.5m per half turn.
+    fn config() -> OdometryConfig {
+        OdometryConfig::new(100, 6.4 / TAU, 1.0 / PI)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    fn drive<'a>(
+        left: &'a [i32],
+        right: &'a [i32],
+    ) -> DifferentialDrive<ScriptedEncoder<'a>, ScriptedEncoder<'a>> {
+        let mut drive = DifferentialDrive::new(
+            ScriptedEncoder::new(left),
+            ScriptedEncoder::new(right),
+            config(),
+            Pose::default(),
+            Instant::from_millis(0),
+        );
+        for update in 1..left.len() {
+            drive.update(Instant::from_millis(100 * update as u64));
+        }
+        drive
+    }
+
+    #[test]
+    fn straight_line() {
+        let drive = drive(&[0, 500, 1000], &[0, 500, 1000]);
+        assert_close(drive.pose().x, 1.0);
+        assert_close(drive.pose().y, 0.0);
+        assert_close(drive.pose().theta, 0.0);
+        assert_close(drive.twist().linear, 5.0);
+        assert_close(drive.twist().angular, 0.0);
+    }
+
+    #[test]
+    fn spin_in_place() {
+        let drive = drive(&[0, -250], &[0, 250]);
+        assert_close(drive.pose().x, 0.0);
+        assert_close(drive.pose().y, 0.0);
+        assert_close(drive.pose().theta, FRAC_PI_2);
+        assert_close(drive.twist().linear, 0.0);
+        assert_close(drive.twist().angular, 10.0 * FRAC_PI_2);
+    }
+
+    #[test]
+    fn high_resolution_encoders() {
+        // counts * sub-steps no longer fits in a u32.
+        let config = OdometryConfig::new(1 << 28, 1.0, 1.0);
+        assert_close(config.to_meters(1 << 30), TAU / 16.0);
+    }
+
+    #[test]
+    fn arc() {
+        // A half circle of radius 1/π around (0, 1/π).
+        // Split over several updates, the result must not depend on the sample rate.
+        let drive = drive(&[0, 100, 300, 500], &[0, 300, 900, 1500]);
+        assert_close(drive.pose().x, 0.0);
+        assert_close(drive.pose().y, 2.0 / PI);
+        assert_close(drive.pose().theta.abs(), PI);
+    }
+
+    #[test]
+    fn wheels_are_timed_by_their_own_samples() {
+        // The right wheel was sampled 50ms after the left on the first update,
+        // both roll 0.5m by their next sample.
+        let left_instants = [0, 100].map(Instant::from_millis);
+        let right_instants = [50, 100].map(Instant::from_millis);
+        let mut drive = DifferentialDrive::new(
+            ScriptedEncoder::new(&[0, 500]).with_instants(&left_instants),
+            ScriptedEncoder::new(&[0, 500]).with_instants(&right_instants),
+            config(),
+            Pose::default(),
+            Instant::from_millis(0),
+        );
+        drive.update(Instant::from_millis(200));
+        // 5m/s on the left and 10m/s on the right.
+        assert_close(drive.twist().linear, 7.5);
+        assert_close(drive.twist().angular, 5.0 * PI);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::Step;
+
+/// Step the PIO counter is seeded with for each pin state, indexed by `B << 1 | A`.
+///
+/// Chosen so the low two bits of the step count always follow the phase, the same as the
+/// upstream C init. That lets the calibration table line up with the physical phases.
+const STEP_OF_PHASE: [u8; 4] = [0, 3, 1, 2];
+
+/// Pin state for each value of the low two bits of the step count, the inverse of [`STEP_OF_PHASE`].
+const PHASE_OF_STEP: [u8; 4] = [0, 2, 3, 1];
+
+/// The step to seed the PIO counter with, `pin_state` is `B << 1 | A`.
+pub fn initial_step(pin_state: u8) -> Step {
+    Step::new(STEP_OF_PHASE[usize::from(pin_state & 0b11)].into())
+}
+
+/// The pin state the encoder is in on `step`, as `B << 1 | A`.
+pub fn phase(step: Step) -> u8 {
+    PHASE_OF_STEP[(step.raw() & 0b11).unsigned_abs() as usize]
+}
+
+/// The first reading after start-up does not match the pins the state machine was seeded from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SyncError {
+    pub expected: Step,
+    pub read: Step,
+}
+
+/// Check the first reading pushed by the state machine against the pin state it was seeded with.
+///
+/// The encoder may have moved by a step in between, anything further means the seed was wrong.
+/// # Errors
+/// The first reading is more than one step away from the seed.
+pub fn check_sync(pin_state: u8, first: Step) -> Result<(), SyncError> {
+    let expected = initial_step(pin_state);
+    if first.raw().wrapping_sub(expected.raw()).unsigned_abs() > 1 {
+        return Err(SyncError {
+            expected,
+            read: first,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SyncError, check_sync, initial_step, phase};
+    use crate::Step;
+
+    #[test]
+    fn phase_table() {
+        for pin_state in 0..4 {
+            assert_eq!(phase(initial_step(pin_state)), pin_state);
+        }
+        // Counting up is a Gray code, exactly one pin changes per step.
+        for step in -4..4 {
+            let change = phase(Step::new(step)) ^ phase(Step::new(step + 1));
+            assert!(change == 0b01 || change == 0b10, "step {step}");
+        }
+    }
+
+    #[test]
+    fn first_reading_must_match_the_seed() {
+        assert_eq!(check_sync(0b01, Step::new(3)), Ok(()));
+        assert_eq!(check_sync(0b01, Step::new(4)), Ok(()));
+        assert_eq!(check_sync(0b00, Step::new(-1)), Ok(()));
+        assert_eq!(
+            check_sync(0b11, Step::new(0)),
+            Err(SyncError {
+                expected: Step::new(2),
+                read: Step::new(0)
+            })
+        );
+    }
+}
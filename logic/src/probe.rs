@@ -0,0 +1,154 @@
+use crate::{CalibrationData, Direction, DirectionDuration, PioTiming, Speed, Step, SubStep};
+use embassy_time::Duration;
+
+/// The probe PIO program takes 14 clock cycles for each loop.
+///
+/// One more than the sub-step program, to check the probe pin.
+pub const PROBE_LOOP_DURATION: u32 = 14;
+
+/// Encoder state latched by the probe PIO program when the probe input became active.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProbeCapture {
+    step: Step,
+    direction: Direction,
+    since_transition: Duration,
+}
+
+impl ProbeCapture {
+    pub fn new(step: Step, direction: Direction, since_transition: Duration) -> Self {
+        Self {
+            step,
+            direction,
+            since_transition,
+        }
+    }
+    /// Decode the two words pushed by the probe program.
+    pub fn from_raw(direction_duration: i32, step: i32, clock_ticks_per_us: u32) -> Self {
+        let (direction, since_transition) = DirectionDuration::new(direction_duration)
+            .decode_with_loop_duration(clock_ticks_per_us, PROBE_LOOP_DURATION);
+        Self::new(Step::new(step), direction, since_transition)
+    }
+    /// Same as [`Self::from_raw`] for an exact clock and any divider,
+    /// `timing` must use [`PROBE_LOOP_DURATION`].
+    pub fn from_raw_with_timing(direction_duration: i32, step: i32, timing: &PioTiming) -> Self {
+        let (direction, since_transition) =
+            DirectionDuration::new(direction_duration).decode_with_timing(timing);
+        Self::new(Step::new(step), direction, since_transition)
+    }
+    pub fn step(&self) -> Step {
+        self.step
+    }
+    /// Direction of the last transition before the capture.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+    /// Time between the last transition and the capture.
+    pub fn since_transition(&self) -> Duration {
+        self.since_transition
+    }
+    /// Sub-step position of the last transition before the capture.
+    pub fn transition(&self, calibration: &CalibrationData) -> SubStep {
+        match self.direction {
+            Direction::Clockwise => self.step.upper_bound(calibration),
+            Direction::CounterClockwise => self.step.lower_bound(calibration),
+        }
+    }
+    /// Estimate the position at the moment of the capture.
+    ///
+    /// The time since the last transition is extrapolated using `speed`.
+    /// The result is clamped to the captured step since the encoder was known to be inside it.
+    pub fn interpolate(&self, speed: Speed, calibration: &CalibrationData) -> SubStep {
+        let range = self.step.substep_range(calibration);
+        let width = (range.end - range.start).raw();
+        // The transition into a step moving clockwise is the step's upper bound,
+        // any other position has to be strictly inside the step.
+        let max_offset = match self.direction {
+            Direction::Clockwise => width,
+            Direction::CounterClockwise => width - 1,
+        };
+        let estimate = self.transition(calibration) + speed * self.since_transition;
+        let offset = (estimate - range.start).raw().clamp(0, max_offset);
+        range.start + SubStep::new(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PROBE_LOOP_DURATION, ProbeCapture};
+    use crate::{Direction, EQUAL_STEPS, Speed, Step, SubStep};
+    use embassy_time::Duration;
+
+    fn step() -> Step {
+        Step::new(5)
+    }
+    /// One step every 2^20µs, so the speed and the tested durations are exact.
+    fn one_step() -> Duration {
+        Duration::from_micros(1 << 20)
+    }
+
+    #[test]
+    fn stopped_encoder_reports_the_transition() {
+        let capture =
+            ProbeCapture::new(step(), Direction::CounterClockwise, Duration::from_secs(1));
+        assert_eq!(
+            capture.interpolate(Speed::stopped(), &EQUAL_STEPS),
+            SubStep::new(5 * 64)
+        );
+        let capture = ProbeCapture::new(step(), Direction::Clockwise, Duration::from_secs(1));
+        assert_eq!(
+            capture.interpolate(Speed::stopped(), &EQUAL_STEPS),
+            SubStep::new(6 * 64)
+        );
+    }
+
+    #[test]
+    fn interpolates_with_speed() {
+        let forward = Speed::new(SubStep::new(64), one_step());
+        let capture = ProbeCapture::new(step(), Direction::CounterClockwise, one_step() / 2);
+        assert_eq!(
+            capture.interpolate(forward, &EQUAL_STEPS),
+            SubStep::new(5 * 64 + 32)
+        );
+
+        let backward = Speed::new(SubStep::new(-64), one_step());
+        let capture = ProbeCapture::new(step(), Direction::Clockwise, one_step() / 2);
+        assert_eq!(
+            capture.interpolate(backward, &EQUAL_STEPS),
+            SubStep::new(6 * 64 - 32)
+        );
+    }
+
+    #[test]
+    fn interpolation_stays_inside_the_captured_step() {
+        let forward = Speed::new(SubStep::new(64), one_step());
+        let backward = Speed::new(SubStep::new(-64), one_step());
+        let late = ProbeCapture::new(step(), Direction::CounterClockwise, one_step() * 5);
+        assert_eq!(
+            late.interpolate(forward, &EQUAL_STEPS),
+            SubStep::new(6 * 64 - 1)
+        );
+        let late = ProbeCapture::new(step(), Direction::Clockwise, one_step() * 5);
+        assert_eq!(
+            late.interpolate(backward, &EQUAL_STEPS),
+            SubStep::new(5 * 64)
+        );
+        // A stale speed pointing the wrong way does not move the position out of the step.
+        assert_eq!(
+            late.interpolate(forward, &EQUAL_STEPS),
+            SubStep::new(6 * 64)
+        );
+    }
+
+    #[test]
+    fn decode_capture() {
+        // Counterclockwise transitions reset X to zero, then it is decremented every loop.
+        let capture = ProbeCapture::from_raw(-10, 5, 7);
+        assert_eq!(capture.step(), step());
+        assert_eq!(capture.direction(), Direction::CounterClockwise);
+        assert_eq!(
+            capture.since_transition(),
+            Duration::from_micros(u64::from(10 * PROBE_LOOP_DURATION / 7))
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use embassy_time::{Duration, Instant};
+
+/// Longest gap between updates that still gives a useful speed estimate.
+///
+/// Slower updates are handled, timer wraps are undone by
+/// [`Measurement::extend_across_wraps`](crate::Measurement::extend_across_wraps),
+/// but the speed is only refreshed once per update so it lags behind changes.
+pub const MAX_UPDATE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Why an update rate is too slow.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RateWarning {
+    /// Updates are further apart than the idle timeout,
+    /// so the encoder can be reported as moving after it stopped.
+    SlowerThanIdleTimeout,
+    /// Updates are further apart than [`MAX_UPDATE_PERIOD`], so the speed is stale.
+    SlowerThanMinimumRate,
+}
+
+/// Check an update period against the limits.
+///
+/// `idle_timeout` is the `IDLE_STOPING_TIME_MS` of the encoder, if known.
+pub fn check_period(period: Duration, idle_timeout: Option<Duration>) -> Option<RateWarning> {
+    if period > MAX_UPDATE_PERIOD {
+        Some(RateWarning::SlowerThanMinimumRate)
+    } else if idle_timeout.is_some_and(|idle_timeout| period > idle_timeout) {
+        Some(RateWarning::SlowerThanIdleTimeout)
+    } else {
+        None
+    }
+}
+
+/// Timing statistics for a fixed rate update loop.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JitterStats {
+    period: Duration,
+    last: Option<Instant>,
+    samples: u32,
+    missed_deadlines: u32,
+    max_early: Duration,
+    max_late: Duration,
+    longest_interval: Duration,
+}
+
+impl JitterStats {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            last: None,
+            samples: 0,
+            missed_deadlines: 0,
+            max_early: Duration::from_ticks(0),
+            max_late: Duration::from_ticks(0),
+            longest_interval: Duration::from_ticks(0),
+        }
+    }
+    /// Record an update that happened at `now`.
+    pub fn record(&mut self, now: Instant) {
+        self.samples = self.samples.saturating_add(1);
+        let Some(last) = self.last.replace(now) else {
+            return;
+        };
+        let interval = now.saturating_duration_since(last);
+        if interval > self.period {
+            self.max_late = self.max_late.max(interval - self.period);
+        } else {
+            self.max_early = self.max_early.max(self.period - interval);
+        }
+        self.longest_interval = self.longest_interval.max(interval);
+        // At least one whole tick was skipped.
+        if interval >= self.period * 2 {
+            self.missed_deadlines = self.missed_deadlines.saturating_add(1);
+        }
+    }
+    /// Clear the statistics, the next update starts a new interval.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.period);
+    }
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+    /// Number of intervals that took at least two periods.
+    pub fn missed_deadlines(&self) -> u32 {
+        self.missed_deadlines
+    }
+    /// Largest amount an interval was shorter than the period.
+    pub fn max_early(&self) -> Duration {
+        self.max_early
+    }
+    /// Largest amount an interval was longer than the period.
+    pub fn max_late(&self) -> Duration {
+        self.max_late
+    }
+    pub fn longest_interval(&self) -> Duration {
+        self.longest_interval
+    }
+    /// Check the longest interval seen so far, see [`check_period`].
+    pub fn rate_warning(&self, idle_timeout: Option<Duration>) -> Option<RateWarning> {
+        check_period(self.longest_interval, idle_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JitterStats, RateWarning, check_period};
+    use embassy_time::{Duration, Instant};
+
+    #[test]
+    fn records_jitter_and_missed_deadlines() {
+        let mut stats = JitterStats::new(Duration::from_millis(10));
+        for ms in [0, 10, 19, 31, 51, 61] {
+            stats.record(Instant::from_millis(ms));
+        }
+        assert_eq!(stats.samples(), 6);
+        assert_eq!(stats.max_early(), Duration::from_millis(1));
+        assert_eq!(stats.max_late(), Duration::from_millis(10));
+        assert_eq!(stats.longest_interval(), Duration::from_millis(20));
+        assert_eq!(stats.missed_deadlines(), 1);
+        assert_eq!(stats.rate_warning(None), None);
+        assert_eq!(
+            stats.rate_warning(Some(Duration::from_millis(15))),
+            Some(RateWarning::SlowerThanIdleTimeout)
+        );
+        stats.reset();
+        assert_eq!(stats.samples(), 0);
+        assert_eq!(stats.longest_interval(), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn period_limits() {
+        let idle = Some(Duration::from_millis(30));
+        assert_eq!(check_period(Duration::from_millis(10), idle), None);
+        assert_eq!(
+            check_period(Duration::from_millis(50), idle),
+            Some(RateWarning::SlowerThanIdleTimeout)
+        );
+        assert_eq!(
+            check_period(Duration::from_millis(200), None),
+            Some(RateWarning::SlowerThanMinimumRate)
+        );
+    }
+}
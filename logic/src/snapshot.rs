@@ -0,0 +1,111 @@
+use embassy_time::{Duration, Instant};
+
+use crate::{Direction, EncoderReader, Speed, Step, SubStep};
+
+/// The state of an encoder after a single update.
+///
+/// Unlike calling [`Encoder::ticks`](crate::Encoder::ticks), [`Encoder::position`](crate::Encoder::position)
+/// and [`Encoder::speed`](crate::Encoder::speed) one after the other,
+/// every field comes from the same measurement, so the values are consistent when logged.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub step: Step,
+    /// Estimated sub-step position at `sample_instant`.
+    pub position: SubStep,
+    pub speed: Speed,
+    pub direction: Direction,
+    /// Time between the last step and `sample_instant`.
+    #[cfg_attr(feature = "serde", serde(with = "micros::duration"))]
+    pub since_transition: Duration,
+    /// When the measurement was read from the PIO.
+    #[cfg_attr(feature = "serde", serde(with = "micros::instant"))]
+    pub sample_instant: Instant,
+}
+
+impl EncoderReader for Snapshot {
+    fn speed(&self) -> Speed {
+        self.speed
+    }
+    fn position(&self) -> SubStep {
+        self.position
+    }
+    fn ticks(&self) -> Step {
+        self.step
+    }
+}
+
+/// embassy-time types do not implement serde, they are stored as micro seconds.
+#[cfg(feature = "serde")]
+mod micros {
+    pub mod duration {
+        use embassy_time::Duration;
+        use serde::{Deserialize, Deserializer, Serializer};
+        #[expect(
+            clippy::trivially_copy_pass_by_ref,
+            reason = "signature required by serde(with)"
+        )]
+        pub fn serialize<S: Serializer>(
+            duration: &Duration,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_u64(duration.as_micros())
+        }
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Duration, D::Error> {
+            u64::deserialize(deserializer).map(Duration::from_micros)
+        }
+    }
+    pub mod instant {
+        use embassy_time::Instant;
+        use serde::{Deserialize, Deserializer, Serializer};
+        #[expect(
+            clippy::trivially_copy_pass_by_ref,
+            reason = "signature required by serde(with)"
+        )]
+        pub fn serialize<S: Serializer>(
+            instant: &Instant,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_u64(instant.as_micros())
+        }
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Instant, D::Error> {
+            u64::deserialize(deserializer).map(Instant::from_micros)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Direction::CounterClockwise,
+        EncoderState, Step,
+        measurement::tests::{Event, sequence_events},
+    };
+    use embassy_time::{Duration, Instant};
+
+    #[test]
+    fn snapshot_matches_the_state() {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Event::Mesurement),
+                (Instant::from_millis(10), Event::Step(2)),
+                (Instant::from_millis(15), Event::Mesurement),
+            ],
+        );
+        let mut state = EncoderState::<30>::new(measurements[0]);
+        state.update(measurements[1]);
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.step, state.steps());
+        assert_eq!(snapshot.position, state.position());
+        assert_eq!(snapshot.speed, state.speed());
+        assert_eq!(snapshot.direction, CounterClockwise);
+        assert_eq!(snapshot.since_transition, Duration::from_millis(5));
+        assert_eq!(snapshot.sample_instant, Instant::from_millis(15));
+    }
+}
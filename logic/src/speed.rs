@@ -29,8 +29,12 @@ use crate::step::SubStep;
 ///```
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Speed(i32);
 
+/// Speed is stored in units of sub-steps per 2^`SPEED_FRACTIONAL_BITS` microseconds.
+pub(crate) const SPEED_FRACTIONAL_BITS: u32 = 20;
+
 fn clamp_cast(value: i64) -> i32 {
     value
         .clamp(i32::MIN.into(), i32::MAX.into())
@@ -41,21 +45,33 @@ fn clamp_cast(value: i64) -> i32 {
 impl Speed {
     ///Create a new speed reading.
     pub fn new(delta: SubStep, duration: Duration) -> Self {
+        Self::from_nanos(delta, duration.as_micros().saturating_mul(1000))
+    }
+    /// Same as [`Self::new`] for a duration in nanoseconds.
+    ///
+    /// # Panics
+    /// Never, the duration is range checked before it is converted.
+    pub fn from_nanos(delta: SubStep, nanos: u64) -> Self {
         let sub_steps = i64::from(delta.raw());
-        let micro_seconds = duration.as_micros();
-        if micro_seconds > i64::MAX as u64 {
+        if nanos > i64::MAX as u64 {
             //Division would round to zero
             Self::stopped()
         } else {
-            let micro_seconds =
-                i64::try_from(micro_seconds).expect("Allready checked that value will fit");
-            let speed = (sub_steps << 20) / micro_seconds;
+            let nanos = i64::try_from(nanos).expect("Allready checked that value will fit");
+            let speed = (sub_steps << SPEED_FRACTIONAL_BITS) * 1000 / nanos;
             Self(clamp_cast(speed))
         }
     }
     pub fn stopped() -> Self {
         Self(0)
     }
+    /// Internal representation, sub-steps per 2^20 microseconds.
+    pub(crate) fn raw(self) -> i32 {
+        self.0
+    }
+    pub(crate) fn from_raw(raw: i32) -> Self {
+        Self(raw)
+    }
 
     ///Maximum speed that is possible to represent
     pub const fn max() -> Self {
@@ -67,22 +83,30 @@ impl Speed {
     }
 }
 
+impl Speed {
+    /// Distance traveled in `nanos` nanoseconds, like multiplying by a [`Duration`].
+    pub fn mul_nanos(self, nanos: u64) -> SubStep {
+        let sub_steps =
+            (i128::from(self.0) * i128::from(nanos)).div_euclid(1000) >> SPEED_FRACTIONAL_BITS;
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "Wraps the same way the sub-step counter does"
+        )]
+        SubStep::new(sub_steps as i32)
+    }
+}
+
 impl Mul<Duration> for Speed {
     type Output = SubStep;
 
     fn mul(self, rhs: Duration) -> Self::Output {
-        #[expect(
-            clippy::suspicious_arithmetic_impl,
-            reason = "Internal storage is in units of 2^20 microseconds"
-        )]
-        //TODO: I don't like this wrapping multiply
-        SubStep::new(((self.0 as u64).wrapping_mul(rhs.as_micros()) >> 20) as i32)
+        self.mul_nanos(rhs.as_micros().saturating_mul(1000))
     }
 }
 #[cfg(test)]
 mod test {
     use super::Speed;
-    use crate::{EQUAL_STEPS, Step, step::SubStep};
+    use crate::step::SubStep;
     use embassy_time::Duration;
 
     #[test]
@@ -3,6 +3,9 @@ use core::{
     num::Wrapping,
     ops::{Add, Range, Sub},
 };
+/// Number of sub-steps in a single step, assuming all steps are the same size.
+pub(crate) const SUBSTEPS_PER_STEP: u32 = 64;
+
 ///An encoder step. (4 steps per encoder cycle)
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Step(Wrapping<u32>);
@@ -30,6 +33,33 @@ mod defmt_impl {
     }
 }
 
+#[cfg(feature = "serde")]
+/// Steps are serialized as their signed value, the same as [`Step::raw`].
+mod serde_impl {
+    use super::{Step, SubStep};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    impl Serialize for Step {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.raw().serialize(serializer)
+        }
+    }
+    impl<'de> Deserialize<'de> for Step {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            i32::deserialize(deserializer).map(Step::new)
+        }
+    }
+    impl Serialize for SubStep {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.raw().serialize(serializer)
+        }
+    }
+    impl<'de> Deserialize<'de> for SubStep {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            i32::deserialize(deserializer).map(SubStep::new)
+        }
+    }
+}
+
 impl Step {
     pub fn new(step: i32) -> Self {
         #[allow(
@@ -69,6 +99,11 @@ impl Step {
             self.0.0 as i32
         }
     }
+    /// The step at the mirrored position when counting in the opposite direction.
+    #[must_use]
+    pub fn mirror(self) -> Self {
+        Self(!self.0)
+    }
     /// Returns the direction of other relative to self via the shortest path.
     /// Returns None if the values are the same.
     pub fn comp(&self, other: Self) -> Option<Direction> {
@@ -183,6 +218,7 @@ mod tests {
     }
 
     #[test]
+    #[expect(clippy::identity_op, reason = "Upstream test, kept as written")]
     fn substeps_role_over_before_steps_do() {
         for i in (32 - 6)..32 {
             dbg!(i);
@@ -0,0 +1,131 @@
+use core::cmp::Ordering;
+
+use crate::{EncoderState, Speed, Step};
+
+/// A condition an async task can wait on.
+///
+/// Created from the encoder state at the start of the wait,
+/// then checked with [`Self::is_met`] after every update.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WaitFor {
+    /// The step count changed.
+    NextStep { from: Step },
+    /// The unwrapped position reached or crossed `target`.
+    Position { target: i64, start: Ordering },
+    /// The magnitude of the speed is above `threshold`.
+    SpeedAbove { threshold: Speed },
+    /// The encoder is stopped.
+    Stopped,
+}
+
+impl WaitFor {
+    pub fn next_step<const IDLE_STOPING_TIME_MS: u64>(
+        state: &EncoderState<IDLE_STOPING_TIME_MS>,
+    ) -> Self {
+        WaitFor::NextStep {
+            from: state.steps(),
+        }
+    }
+    /// `target` is an unwrapped sub-step position, see [`EncoderState::unwrapped_position`].
+    ///
+    /// Crossing is detected from either side, so the direction of travel does not matter.
+    pub fn position<const IDLE_STOPING_TIME_MS: u64>(
+        state: &EncoderState<IDLE_STOPING_TIME_MS>,
+        target: i64,
+    ) -> Self {
+        WaitFor::Position {
+            target,
+            start: state.unwrapped_position().cmp(&target),
+        }
+    }
+    /// Works in either direction, only the magnitude of `threshold` is used.
+    pub fn speed_above(threshold: Speed) -> Self {
+        WaitFor::SpeedAbove { threshold }
+    }
+    pub fn stopped() -> Self {
+        WaitFor::Stopped
+    }
+
+    pub fn is_met<const IDLE_STOPING_TIME_MS: u64>(
+        &self,
+        state: &EncoderState<IDLE_STOPING_TIME_MS>,
+    ) -> bool {
+        match *self {
+            WaitFor::NextStep { from } => state.steps() != from,
+            WaitFor::Position { target, start } => {
+                start == Ordering::Equal || state.unwrapped_position().cmp(&target) != start
+            }
+            WaitFor::SpeedAbove { threshold } => {
+                state.speed().raw().unsigned_abs() > threshold.raw().unsigned_abs()
+            }
+            WaitFor::Stopped => state.speed() == Speed::stopped(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WaitFor;
+    use crate::{
+        Direction::CounterClockwise,
+        EncoderState, Speed, Step, SubStep,
+        measurement::tests::{Event, sequence_events},
+    };
+    use embassy_time::{Duration, Instant};
+
+    fn states() -> Vec<EncoderState<30>> {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Event::Mesurement),
+                (Instant::from_millis(10), Event::Step(2)),
+                (Instant::from_millis(10), Event::Mesurement),
+                (Instant::from_millis(20), Event::Step(4)),
+                (Instant::from_millis(20), Event::Mesurement),
+                (Instant::from_millis(60), Event::Mesurement),
+            ],
+        );
+        let mut state = EncoderState::<30>::new(measurements[0]);
+        let mut states = vec![state.clone()];
+        for measurement in &measurements[1..] {
+            state.update(*measurement);
+            states.push(state.clone());
+        }
+        states
+    }
+
+    #[test]
+    fn next_step() {
+        let states = states();
+        let condition = WaitFor::next_step(&states[0]);
+        assert!(!condition.is_met(&states[0]));
+        assert!(condition.is_met(&states[1]));
+    }
+
+    #[test]
+    fn position_crossing() {
+        let states = states();
+        let condition = WaitFor::position(&states[0], 3 * 64);
+        assert!(!condition.is_met(&states[0]));
+        assert!(!condition.is_met(&states[1]));
+        assert!(condition.is_met(&states[2]));
+        // Crossing back the other way.
+        let condition = WaitFor::position(&states[2], 64);
+        assert!(!condition.is_met(&states[2]));
+        assert!(condition.is_met(&states[0]));
+        // Already there.
+        assert!(WaitFor::position(&states[0], 0).is_met(&states[0]));
+    }
+
+    #[test]
+    fn speed_and_stopping() {
+        let states = states();
+        let threshold =
+            WaitFor::speed_above(Speed::new(SubStep::new(-64), Duration::from_millis(10)));
+        assert!(!threshold.is_met(&states[0]));
+        assert!(threshold.is_met(&states[1]));
+        assert!(WaitFor::stopped().is_met(&states[0]));
+        assert!(!WaitFor::stopped().is_met(&states[2]));
+        assert!(WaitFor::stopped().is_met(&states[3]));
+    }
+}
@@ -0,0 +1,108 @@
+use embassy_time::{Duration, Instant};
+
+use crate::{Direction, EQUAL_STEPS, Speed, Step, SubStep};
+
+/// Speed estimate for encoders that only report a step count.
+///
+/// Counts the steps over a fixed window of time, the estimate is refreshed each time a window
+/// completes. Resolution is one step per window, so longer windows are smoother but slower to react.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WindowSpeed {
+    window: Duration,
+    window_start: (Step, Instant),
+    step: Step,
+    direction: Direction,
+    speed: Speed,
+}
+
+impl WindowSpeed {
+    /// # Panics
+    /// If `window` is zero.
+    pub fn new(window: Duration, step: Step, now: Instant) -> Self {
+        assert!(window.as_ticks() > 0, "The window must not be empty");
+        Self {
+            window,
+            window_start: (step, now),
+            step,
+            direction: Direction::CounterClockwise,
+            speed: Speed::stopped(),
+        }
+    }
+    /// Record the step count read at `now`.
+    pub fn update(&mut self, step: Step, now: Instant) {
+        if step != self.step
+            && let Some(direction) = self.step.comp(step)
+        {
+            self.direction = direction;
+        }
+        self.step = step;
+        let (start_step, start) = self.window_start;
+        let elapsed = now.saturating_duration_since(start);
+        if elapsed >= self.window {
+            let steps = step.raw().wrapping_sub(start_step.raw());
+            self.speed = Speed::new(
+                SubStep::new(steps.wrapping_mul(crate::step::SUBSTEPS_PER_STEP.cast_signed())),
+                elapsed,
+            );
+            self.window_start = (step, now);
+        }
+    }
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+    /// Average speed over the last complete window.
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+    pub fn steps(&self) -> Step {
+        self.step
+    }
+    /// Direction of the most recent step.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+    /// Start of the current step, there is no sub-step interpolation.
+    pub fn position(&self) -> SubStep {
+        self.step.lower_bound(&EQUAL_STEPS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WindowSpeed;
+    use crate::{Direction, Speed, Step, SubStep};
+    use embassy_time::{Duration, Instant};
+
+    #[test]
+    fn counts_steps_over_the_window() {
+        let window = Duration::from_millis(100);
+        let mut estimator = WindowSpeed::new(window, Step::new(0), Instant::from_millis(0));
+        estimator.update(Step::new(3), Instant::from_millis(50));
+        // Window not complete yet.
+        assert_eq!(estimator.speed(), Speed::stopped());
+        assert_eq!(estimator.position(), SubStep::new(3 * 64));
+
+        estimator.update(Step::new(10), Instant::from_millis(100));
+        assert_eq!(estimator.speed(), Speed::new(SubStep::new(10 * 64), window));
+        assert_eq!(estimator.direction(), Direction::CounterClockwise);
+        estimator.update(Step::new(-2), Instant::from_millis(200));
+        assert_eq!(estimator.direction(), Direction::Clockwise);
+        assert_eq!(
+            estimator.speed(),
+            Speed::new(SubStep::new(-12 * 64), window)
+        );
+        estimator.update(Step::new(-2), Instant::from_millis(300));
+        assert_eq!(estimator.speed(), Speed::stopped());
+    }
+
+    #[test]
+    #[should_panic(expected = "The window must not be empty")]
+    fn empty_window() {
+        let _ = WindowSpeed::new(
+            Duration::from_ticks(0),
+            Step::new(0),
+            Instant::from_millis(0),
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{Step, phase};
+
+/// A wiring problem found by [`PullTest`] or [`WiringMonitor`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WiringFault {
+    /// Neither channel is connected, or the encoder is not powered.
+    Disconnected,
+    /// Channel A never changes.
+    StuckA,
+    /// Channel B never changes.
+    StuckB,
+}
+
+/// Result of briefly switching each input from a pull up to a pull down.
+///
+/// A driven input ignores the pull, a floating one follows it and the PIO counts a step.
+/// The encoder must be stationary during the test.
+/// Encoders with open collector outputs rely on the pull up, so they cannot be tested this way.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PullTest {
+    /// Step before any pull was changed.
+    pub before: Step,
+    /// Step while A was pulled down.
+    pub a_pulled_down: Step,
+    /// Step while B was pulled down.
+    pub b_pulled_down: Step,
+}
+
+impl PullTest {
+    pub fn fault(&self) -> Option<WiringFault> {
+        let a_floating = self.a_pulled_down != self.before;
+        let b_floating = self.b_pulled_down != self.before;
+        match (a_floating, b_floating) {
+            (true, true) => Some(WiringFault::Disconnected),
+            (true, false) => Some(WiringFault::StuckA),
+            (false, true) => Some(WiringFault::StuckB),
+            (false, false) => None,
+        }
+    }
+}
+
+/// Watches the steps read while the encoder is commanded to move.
+///
+/// No steps at all means the encoder is disconnected. With one channel stuck the count
+/// only rocks back and forth between two neighbouring steps, the pair tells which channel it is.
+/// Steps must be raw PIO steps or have a [`CountDirection`](crate::CountDirection) applied,
+/// both keep the phases lined up.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WiringMonitor {
+    last: Step,
+    /// Travel from the first step, so wrapping counters do not matter.
+    offset: i64,
+    lowest: (i64, Step),
+    highest: i64,
+    changes: u32,
+    min_changes: u32,
+}
+
+impl WiringMonitor {
+    pub fn new(start: Step) -> Self {
+        Self {
+            last: start,
+            offset: 0,
+            lowest: (0, start),
+            highest: 0,
+            changes: 0,
+            min_changes: 4,
+        }
+    }
+    /// How many step changes are needed before blaming a stuck channel, defaults to 4.
+    #[must_use]
+    pub fn with_min_changes(mut self, min_changes: u32) -> Self {
+        self.min_changes = min_changes;
+        self
+    }
+    /// Record the step read after an update.
+    pub fn record(&mut self, step: Step) {
+        if step == self.last {
+            return;
+        }
+        self.changes = self.changes.saturating_add(1);
+        self.offset += i64::from(step.raw().wrapping_sub(self.last.raw()));
+        self.last = step;
+        if self.offset < self.lowest.0 {
+            self.lowest = (self.offset, step);
+        }
+        self.highest = self.highest.max(self.offset);
+    }
+    /// Judge the wiring, call once the commanded motion should have produced steps.
+    pub fn fault(&self) -> Option<WiringFault> {
+        match self.highest - self.lowest.0 {
+            0 => Some(WiringFault::Disconnected),
+            1 if self.changes >= self.min_changes => {
+                let low = self.lowest.1;
+                let high = Step::new(low.raw().wrapping_add(1));
+                // The channel that does change is the one whose bit differs.
+                if (phase(low) ^ phase(high)) & 0b01 == 0 {
+                    Some(WiringFault::StuckA)
+                } else {
+                    Some(WiringFault::StuckB)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PullTest, WiringFault, WiringMonitor};
+    use crate::{Step, initial_step, phase};
+
+    #[test]
+    fn pull_test() {
+        let test = |a, b| {
+            PullTest {
+                before: Step::new(0),
+                a_pulled_down: Step::new(a),
+                b_pulled_down: Step::new(b),
+            }
+            .fault()
+        };
+        assert_eq!(test(0, 0), None);
+        assert_eq!(test(1, -1), Some(WiringFault::Disconnected));
+        assert_eq!(test(-1, 0), Some(WiringFault::StuckA));
+        assert_eq!(test(0, 1), Some(WiringFault::StuckB));
+    }
+
+    /// Steps the PIO counts when only the channel in `moving` (a pin bit) toggles.
+    fn rocking(moving: u8) -> [Step; 6] {
+        let start = initial_step(0b00);
+        let other = [Step::new(1), Step::new(-1)]
+            .into_iter()
+            .find(|step| phase(*step) == moving)
+            .unwrap();
+        [start, other, start, other, start, other]
+    }
+
+    #[test]
+    fn stuck_channels() {
+        let mut monitor = WiringMonitor::new(initial_step(0b00));
+        for step in rocking(0b10) {
+            monitor.record(step);
+        }
+        // Only B moves.
+        assert_eq!(monitor.fault(), Some(WiringFault::StuckA));
+
+        let mut monitor = WiringMonitor::new(initial_step(0b00));
+        for step in rocking(0b01) {
+            monitor.record(step);
+        }
+        assert_eq!(monitor.fault(), Some(WiringFault::StuckB));
+    }
+
+    #[test]
+    fn healthy_and_disconnected() {
+        let mut monitor = WiringMonitor::new(Step::new(i32::MAX - 2));
+        assert_eq!(monitor.fault(), Some(WiringFault::Disconnected));
+        for step in [i32::MAX - 1, i32::MAX, i32::MIN] {
+            monitor.record(Step::new(step));
+        }
+        assert_eq!(monitor.fault(), None);
+        // A single step is not enough to blame a channel.
+        let mut monitor = WiringMonitor::new(Step::new(0));
+        monitor.record(Step::new(1));
+        assert_eq!(monitor.fault(), None);
+    }
+}
@@ -0,0 +1,551 @@
+use core::ops::Range;
+
+use embassy_time::{Duration, Instant};
+
+use crate::{EncoderReader, EncoderState, SubStep};
+
+/// A named window of positions.
+///
+/// Positions are unwrapped sub-steps, see [`EncoderState::unwrapped_position`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Zone {
+    pub name: &'static str,
+    pub range: Range<i64>,
+}
+
+impl Zone {
+    pub const fn new(name: &'static str, range: Range<i64>) -> Self {
+        Self { name, range }
+    }
+    fn contains(&self, position: i64) -> bool {
+        self.range.contains(&position)
+    }
+}
+
+/// Software end stops, positions outside of `lower..=upper` are considered over travel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SoftLimits {
+    pub lower: i64,
+    pub upper: i64,
+}
+
+impl SoftLimits {
+    fn exceeded(&self, position: i64) -> Option<Limit> {
+        if position < self.lower {
+            Some(Limit::Lower)
+        } else if position > self.upper {
+            Some(Limit::Upper)
+        } else {
+            None
+        }
+    }
+    fn threshold(&self, limit: Limit) -> i64 {
+        match limit {
+            Limit::Lower => self.lower,
+            // The upper limit is inclusive, so it is exceeded when crossing upper + 1.
+            Limit::Upper => self.upper + 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Limit {
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MonitorEvent {
+    Entered(&'static str),
+    Left(&'static str),
+    LimitExceeded(Limit),
+    /// Position has returned within the soft limits.
+    LimitCleared(Limit),
+}
+
+/// An event and the (interpolated) time it happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ZoneEvent {
+    pub event: MonitorEvent,
+    pub at: Instant,
+}
+
+/// Piecewise linear path the encoder took between two updates.
+///
+/// Made up of the previous estimated position, the most recent transition and the current
+/// estimated position.
+#[derive(Clone, Copy, Debug)]
+struct Path {
+    points: [(Instant, i64); 3],
+}
+
+impl Path {
+    /// Earliest time the path moves from one side of `threshold` to the other.
+    ///
+    /// "Sides" are `< threshold` and `>= threshold`.
+    fn crossing(&self, threshold: i64) -> Option<Instant> {
+        self.points.windows(2).find_map(|segment| {
+            let (t_a, p_a) = segment[0];
+            let (t_b, p_b) = segment[1];
+            if (p_a < threshold) == (p_b < threshold) {
+                return None;
+            }
+            let span = (t_b - t_a).as_ticks();
+            let fraction_num = i128::from(threshold - p_a);
+            let fraction_den = i128::from(p_b - p_a);
+            let offset = i128::from(span) * fraction_num / fraction_den;
+            Some(t_a + Duration::from_ticks(u64::try_from(offset).unwrap_or(0)))
+        })
+    }
+}
+
+/// Events generated by a single [`ZoneMonitor::update`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZoneEvents<const N: usize> {
+    entered: [Option<Instant>; N],
+    left: [Option<Instant>; N],
+    names: [&'static str; N],
+    /// Jumping from one limit past the other clears one and exceeds the other,
+    /// kept in the order they were crossed.
+    limits: [Option<ZoneEvent>; 2],
+}
+
+impl<const N: usize> ZoneEvents<N> {
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+    /// Soft limit events from this update, in the order they happened.
+    ///
+    /// There are two when the position jumps from past one limit to past the other.
+    pub fn limits(&self) -> impl Iterator<Item = ZoneEvent> + '_ {
+        self.limits.iter().flatten().copied()
+    }
+    /// All events of this update, in the order they happened.
+    ///
+    /// Events at the same time keep a fixed order: the limit events first, then the zones
+    /// in order, each entered before it is left.
+    pub fn iter(&self) -> impl Iterator<Item = ZoneEvent> + '_ {
+        let mut yielded: Option<(Instant, usize)> = None;
+        // There are at most 2N + 2 events, so picking the next one by scanning is cheap
+        // and needs no buffer.
+        core::iter::from_fn(move || {
+            let (at, slot, event) = (0..2 * N + 2)
+                .filter_map(|slot| self.slot(slot).map(|event| (event.at, slot, event)))
+                .filter(|(at, slot, _)| yielded.is_none_or(|last| (*at, *slot) > last))
+                .min_by_key(|(at, slot, _)| (*at, *slot))?;
+            yielded = Some((at, slot));
+            Some(event)
+        })
+    }
+    /// Slots 0 and 1 are the limit events, zone `i` is entered in slot `2i + 2`
+    /// and left in slot `2i + 3`.
+    fn slot(&self, slot: usize) -> Option<ZoneEvent> {
+        let Some(zone) = slot.checked_sub(2) else {
+            return self.limits[slot];
+        };
+        let i = zone / 2;
+        if zone % 2 == 0 {
+            self.entered[i].map(|at| ZoneEvent {
+                event: MonitorEvent::Entered(self.names[i]),
+                at,
+            })
+        } else {
+            self.left[i].map(|at| ZoneEvent {
+                event: MonitorEvent::Left(self.names[i]),
+                at,
+            })
+        }
+    }
+}
+
+/// Watches an encoder's position against soft limits and named zones.
+///
+/// Positions are only known at each update, and the path in between is assumed to be
+/// a straight line (through the latest transition when updated from an [`EncoderState`]).
+/// A zone that is entered and left again on the same side within one update is not seen,
+/// update at least as often as the shortest time spent in a zone.
+pub struct ZoneMonitor<const N: usize> {
+    zones: [Zone; N],
+    limits: Option<SoftLimits>,
+    inside: [bool; N],
+    exceeded: Option<Limit>,
+    prev: Option<(Instant, i64)>,
+    /// Wrapping position of the previous update, to unwrap [`EncoderReader::position`].
+    prev_position: SubStep,
+}
+
+impl<const N: usize> ZoneMonitor<N> {
+    pub fn new(zones: [Zone; N]) -> Self {
+        Self {
+            zones,
+            limits: None,
+            inside: [false; N],
+            exceeded: None,
+            prev: None,
+            prev_position: SubStep::new(0),
+        }
+    }
+    #[must_use]
+    pub fn with_limits(mut self, limits: SoftLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+    pub fn zones(&self) -> &[Zone; N] {
+        &self.zones
+    }
+    /// Is the position currently inside the named zone?
+    pub fn is_inside(&self, name: &str) -> bool {
+        self.zones
+            .iter()
+            .zip(self.inside)
+            .any(|(zone, inside)| inside && zone.name == name)
+    }
+    /// The soft limit that is currently being exceeded.
+    pub fn exceeded_limit(&self) -> Option<Limit> {
+        self.exceeded
+    }
+
+    /// Check the position of any encoder at `now` and report anything that changed
+    /// since the last call.
+    ///
+    /// The position is unwrapped by following it from update to update, so the encoder must
+    /// move less than half the sub-step range in between.
+    /// The first call only records the starting state (entering a zone or being past a limit
+    /// is still reported, timestamped at `now`).
+    pub fn update(&mut self, encoder: &impl EncoderReader, now: Instant) -> ZoneEvents<N> {
+        let position = encoder.position();
+        let unwrapped = match self.prev {
+            Some((_, prev)) => prev + i64::from((position - self.prev_position).raw()),
+            None => i64::from(position.raw()),
+        };
+        let now = (now, unwrapped);
+        let prev = self.prev.unwrap_or(now);
+        self.check(
+            Path {
+                points: [prev, prev, now],
+            },
+            position,
+        )
+    }
+
+    /// Same as [`Self::update`], but uses the latest transition of the sub-step encoder so
+    /// crossing times are more accurate.
+    ///
+    /// Positions are [`EncoderState::unwrapped_position`]s and are timestamped at the sample time.
+    pub fn update_state<const IDLE_STOPING_TIME_MS: u64>(
+        &mut self,
+        state: &EncoderState<IDLE_STOPING_TIME_MS>,
+    ) -> ZoneEvents<N> {
+        let measurement = state.last_measurement();
+        let now = (measurement.sample_instant, state.unwrapped_position());
+        let transition = (measurement.step_instant, state.unwrapped_transition());
+        let prev = self.prev.unwrap_or(now);
+        // Only use the transition if it happened since the last update.
+        let path = Path {
+            points: if transition.0 > prev.0 {
+                [prev, transition, now]
+            } else {
+                [prev, prev, now]
+            },
+        };
+        self.check(path, state.position())
+    }
+
+    /// Compare the end of `path` against the previous update, `position` is its wrapping position.
+    fn check(&mut self, path: Path, position: SubStep) -> ZoneEvents<N> {
+        let [prev, _, now] = path.points;
+        self.prev_position = position;
+        self.prev = Some(now);
+        let crossing = |threshold| path.crossing(threshold).unwrap_or(now.0);
+
+        let mut events = ZoneEvents {
+            entered: [None; N],
+            left: [None; N],
+            names: core::array::from_fn(|i| self.zones[i].name),
+            limits: [None; 2],
+        };
+
+        for (i, zone) in self.zones.iter().enumerate() {
+            let was_inside = self.inside[i];
+            let is_inside = zone.contains(now.1);
+            let boundary = |position: i64| {
+                if position < zone.range.start {
+                    zone.range.start
+                } else {
+                    zone.range.end
+                }
+            };
+            match (was_inside, is_inside) {
+                (false, true) => events.entered[i] = Some(crossing(boundary(prev.1))),
+                (true, false) => events.left[i] = Some(crossing(boundary(now.1))),
+                (false, false) => {
+                    // Passed through the zone in between updates.
+                    if boundary(prev.1) != boundary(now.1) {
+                        events.entered[i] = Some(crossing(boundary(prev.1)));
+                        events.left[i] = Some(crossing(boundary(now.1)));
+                    }
+                }
+                (true, true) => {}
+            }
+            self.inside[i] = is_inside;
+        }
+
+        if let Some(limits) = self.limits {
+            let exceeded = limits.exceeded(now.1);
+            if exceeded != self.exceeded {
+                let cleared = self.exceeded.map(|limit| ZoneEvent {
+                    event: MonitorEvent::LimitCleared(limit),
+                    at: crossing(limits.threshold(limit)),
+                });
+                let exceeded = exceeded.map(|limit| ZoneEvent {
+                    event: MonitorEvent::LimitExceeded(limit),
+                    at: crossing(limits.threshold(limit)),
+                });
+                events.limits = match (cleared, exceeded) {
+                    (Some(cleared), Some(exceeded)) if exceeded.at < cleared.at => {
+                        [Some(exceeded), Some(cleared)]
+                    }
+                    (cleared, exceeded) => [cleared, exceeded],
+                };
+            }
+            self.exceeded = exceeded;
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limit, MonitorEvent, SoftLimits, Zone, ZoneEvent, ZoneMonitor};
+    use crate::{
+        Direction::CounterClockwise,
+        Encoder, EncoderState, Step,
+        measurement::tests::{Event as Hw, sequence_events},
+        mock::ScriptedEncoder,
+    };
+    use embassy_time::Instant;
+
+    fn monitor() -> ZoneMonitor<2> {
+        ZoneMonitor::new([
+            Zone::new("loading", 64 * 2..64 * 4),
+            Zone::new("cutting", 64 * 10..64 * 20),
+        ])
+        .with_limits(SoftLimits {
+            lower: -64 * 5,
+            upper: 64 * 30,
+        })
+    }
+
+    #[test]
+    fn enter_and_leave_zone() {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Hw::Mesurement),
+                (Instant::from_millis(10), Hw::Step(2)),
+                (Instant::from_millis(10), Hw::Mesurement),
+                (Instant::from_millis(20), Hw::Step(4)),
+                (Instant::from_millis(25), Hw::Mesurement),
+            ],
+        );
+        let mut monitor = monitor();
+        let mut state = EncoderState::<30>::new(measurements[0]);
+        assert!(monitor.update_state(&state).is_empty());
+
+        state.update(measurements[1]);
+        let events = monitor.update_state(&state);
+        assert_eq!(
+            events.iter().collect::<Vec<_>>(),
+            vec![ZoneEvent {
+                event: MonitorEvent::Entered("loading"),
+                at: Instant::from_millis(10)
+            }]
+        );
+        assert!(monitor.is_inside("loading"));
+
+        state.update(measurements[2]);
+        let events = monitor.update_state(&state);
+        assert_eq!(
+            events.iter().collect::<Vec<_>>(),
+            vec![ZoneEvent {
+                event: MonitorEvent::Left("loading"),
+                at: Instant::from_millis(20)
+            }]
+        );
+        assert!(!monitor.is_inside("loading"));
+    }
+
+    #[test]
+    fn crossing_time_is_interpolated() {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Hw::Mesurement),
+                (Instant::from_millis(10), Hw::Step(1)),
+                (Instant::from_millis(10), Hw::Mesurement),
+                // Moving at 1 step per 10 ms, jumping through the whole "cutting" zone.
+                (Instant::from_millis(210), Hw::Step(21)),
+                (Instant::from_millis(210), Hw::Mesurement),
+            ],
+        );
+        let mut monitor = monitor();
+        let mut state = EncoderState::<300>::new(measurements[0]);
+        monitor.update_state(&state);
+        state.update(measurements[1]);
+        let events = monitor.update_state(&state);
+        assert!(events.is_empty());
+
+        state.update(measurements[2]);
+        let events = monitor.update_state(&state).iter().collect::<Vec<_>>();
+        assert_eq!(events.len(), 4);
+        // Linearly interpolated between step 1 at 10ms and step 21 at 210ms
+        assert_eq!(
+            events[0],
+            ZoneEvent {
+                event: MonitorEvent::Entered("loading"),
+                at: Instant::from_millis(20)
+            }
+        );
+        assert_eq!(
+            events[1],
+            ZoneEvent {
+                event: MonitorEvent::Left("loading"),
+                at: Instant::from_millis(40)
+            }
+        );
+        assert_eq!(
+            events[2],
+            ZoneEvent {
+                event: MonitorEvent::Entered("cutting"),
+                at: Instant::from_millis(100)
+            }
+        );
+        assert_eq!(
+            events[3],
+            ZoneEvent {
+                event: MonitorEvent::Left("cutting"),
+                at: Instant::from_millis(200)
+            }
+        );
+    }
+
+    #[test]
+    fn soft_limits() {
+        let measurements = sequence_events(
+            (Step::new(0), CounterClockwise, Instant::from_millis(0)),
+            vec![
+                (Instant::from_millis(0), Hw::Mesurement),
+                (Instant::from_millis(10), Hw::Step(-6)),
+                (Instant::from_millis(15), Hw::Mesurement),
+                (Instant::from_millis(30), Hw::Step(-5)),
+                (Instant::from_millis(35), Hw::Mesurement),
+            ],
+        );
+        let mut monitor = monitor();
+        let mut state = EncoderState::<30>::new(measurements[0]);
+        monitor.update_state(&state);
+        assert_eq!(monitor.exceeded_limit(), None);
+
+        state.update(measurements[1]);
+        let events = monitor.update_state(&state);
+        assert_eq!(
+            events.limits().collect::<Vec<_>>(),
+            vec![ZoneEvent {
+                event: MonitorEvent::LimitExceeded(Limit::Lower),
+                // Moving clockwise so position -64*5 is reached at the transition into step -6.
+                at: Instant::from_millis(10),
+            }]
+        );
+        assert_eq!(monitor.exceeded_limit(), Some(Limit::Lower));
+
+        state.update(measurements[2]);
+        let events = monitor.update_state(&state);
+        assert_eq!(
+            events.limits().collect::<Vec<_>>(),
+            vec![ZoneEvent {
+                event: MonitorEvent::LimitCleared(Limit::Lower),
+                at: Instant::from_millis(30),
+            }]
+        );
+        assert_eq!(monitor.exceeded_limit(), None);
+    }
+
+    #[test]
+    fn any_encoder_in_crossing_order() {
+        let mut monitor = monitor();
+        let mut encoder = ScriptedEncoder::new(&[0, 3200]);
+        assert!(monitor.update(&encoder, Instant::from_millis(0)).is_empty());
+        encoder.update();
+        let events = monitor
+            .update(&encoder, Instant::from_millis(100))
+            .iter()
+            .collect::<Vec<_>>();
+        let expected = [
+            (MonitorEvent::Entered("loading"), 4),
+            (MonitorEvent::Left("loading"), 8),
+            (MonitorEvent::Entered("cutting"), 20),
+            (MonitorEvent::Left("cutting"), 40),
+        ];
+        for (event, (expected, at)) in events.iter().zip(expected) {
+            assert_eq!(event.event, expected);
+            assert_eq!(event.at, Instant::from_millis(at));
+        }
+        // The limit is crossed last, so it is reported last.
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[4].event, MonitorEvent::LimitExceeded(Limit::Upper));
+        assert!(events[4].at > events[3].at);
+    }
+
+    #[test]
+    fn positions_are_unwrapped() {
+        let seam = i64::from(i32::MAX);
+        let mut monitor = ZoneMonitor::new([Zone::new("seam", seam + 64..seam + 128)]);
+        let mut encoder = ScriptedEncoder::new(&[i32::MAX - 64, i32::MIN + 100]);
+        monitor.update(&encoder, Instant::from_millis(0));
+        encoder.update();
+        monitor.update(&encoder, Instant::from_millis(10));
+        assert!(monitor.is_inside("seam"));
+    }
+
+    #[test]
+    fn jump_across_both_limits() {
+        let mut monitor = ZoneMonitor::<0>::new([]).with_limits(SoftLimits {
+            lower: 0,
+            upper: 99,
+        });
+        let mut encoder = ScriptedEncoder::new(&[-100, 200, -100]);
+        monitor.update(&encoder, Instant::from_millis(0));
+        assert_eq!(monitor.exceeded_limit(), Some(Limit::Lower));
+        encoder.update();
+        let events = monitor.update(&encoder, Instant::from_millis(300));
+        assert_eq!(
+            events.iter().collect::<Vec<_>>(),
+            vec![
+                ZoneEvent {
+                    event: MonitorEvent::LimitCleared(Limit::Lower),
+                    at: Instant::from_millis(100),
+                },
+                ZoneEvent {
+                    event: MonitorEvent::LimitExceeded(Limit::Upper),
+                    at: Instant::from_millis(200),
+                },
+            ]
+        );
+        assert_eq!(monitor.exceeded_limit(), Some(Limit::Upper));
+        // And back the other way, the upper limit is crossed first.
+        encoder.update();
+        let events = monitor.update(&encoder, Instant::from_millis(600));
+        assert_eq!(
+            events.limits().map(|event| event.event).collect::<Vec<_>>(),
+            vec![
+                MonitorEvent::LimitCleared(Limit::Upper),
+                MonitorEvent::LimitExceeded(Limit::Lower),
+            ]
+        );
+    }
+}
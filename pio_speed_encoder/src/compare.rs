@@ -0,0 +1,207 @@
+use embassy_rp::{
+    Peri,
+    gpio::{Level, Pull},
+    pio::{
+        Common, Config, Direction, FifoJoin, Instance, Irq, LoadedProgram, PioPin, ShiftDirection,
+        StateMachine,
+        program::{
+            InSource, Instruction, InstructionOperands, JmpCondition, MovDestination, MovOperation,
+            MovSource, OutDestination, SideSet, pio_file,
+        },
+    },
+};
+pub use pio_speed_encoder_logic::compare::{PitchTargets, TARGET_QUEUE_DEPTH};
+use pio_speed_encoder_logic::{
+    Step,
+    compare::{LOAD, LoadInstruction, park},
+};
+
+pub struct PositionCompareProgram<'a, PIO: Instance> {
+    prg: LoadedProgram<'a, PIO>,
+}
+
+impl<'a, PIO: Instance> PositionCompareProgram<'a, PIO> {
+    /// Load the program into the given pio
+    ///
+    /// The program is loaded at address 0 and uses 31 instructions,
+    /// so it needs a PIO block to itself.
+    pub fn new(common: &mut Common<'a, PIO>) -> Self {
+        let prg = pio_file!("src/quadrature_encoder_compare.pio");
+        let prg = common.load_program(&prg.program);
+        Self { prg }
+    }
+}
+
+/// Quadrature encoder reader that pulses an output pin when the count reaches a target.
+///
+/// The comparison is done by the state machine, so the output is not affected by CPU jitter.
+/// The pin is held high for 24 state machine cycles and the PIO IRQ matching the state machine
+/// number is raised on every match.
+///
+/// The pins are not sampled while the pulse is generated, so each match costs about 25 extra
+/// cycles. The fastest step rate is lower than with the plain program, and a step that comes
+/// in sooner than that after a match is missed.
+pub struct PositionCompare<'d, T: Instance, const SM: usize> {
+    sm: StateMachine<'d, T, SM>,
+    irq: Irq<'d, T, SM>,
+    side_set: SideSet,
+}
+
+impl<'d, T: Instance, const SM: usize> PositionCompare<'d, T, SM> {
+    /// Configure a state machine with the loaded [`PositionCompareProgram`].
+    ///
+    /// The count starts at zero and `target` is armed.
+    #[expect(
+        clippy::too_many_arguments,
+        reason = "Mirrors the hardware resources used"
+    )]
+    pub fn new(
+        pio: &mut Common<'d, T>,
+        mut sm: StateMachine<'d, T, SM>,
+        irq: Irq<'d, T, SM>,
+        pin_a: Peri<'d, impl PioPin + 'd>,
+        pin_b: Peri<'d, impl PioPin + 'd>,
+        output: Peri<'d, impl PioPin + 'd>,
+        program: &PositionCompareProgram<'d, T>,
+        target: Step,
+    ) -> Self {
+        let mut pin_a = pio.make_pio_pin(pin_a);
+        let mut pin_b = pio.make_pio_pin(pin_b);
+        let output = pio.make_pio_pin(output);
+        pin_a.set_pull(Pull::Up);
+        pin_b.set_pull(Pull::Up);
+        sm.set_pin_dirs(Direction::In, &[&pin_a, &pin_b]);
+        sm.set_pin_dirs(Direction::Out, &[&output]);
+        sm.set_pins(Level::Low, &[&output]);
+
+        let mut cfg = Config::default();
+        cfg.set_in_pins(&[&pin_a, &pin_b]);
+        cfg.fifo_join = FifoJoin::Duplex;
+        cfg.shift_in.direction = ShiftDirection::Left;
+
+        cfg.use_program(&program.prg, &[&output]);
+        sm.set_config(&cfg);
+
+        let mut encoder = Self {
+            sm,
+            irq,
+            side_set: program.prg.side_set,
+        };
+        encoder.load_target(target);
+        encoder.sm.set_enable(true);
+        encoder
+    }
+
+    /// Replace the armed target, any queued targets are discarded.
+    ///
+    /// If the state machine is in the middle of a match the pulse is cut short.
+    pub fn set_target(&mut self, target: Step) {
+        critical_section::with(|_| {
+            self.sm.set_enable(false);
+            self.sm.clear_fifos();
+            self.load_target(target);
+            self.sm.set_enable(true);
+        });
+    }
+
+    /// Queue a target to be armed after the current one fires.
+    ///
+    /// Returns false if the queue already holds [`TARGET_QUEUE_DEPTH`] targets.
+    pub fn queue(&mut self, target: Step) -> bool {
+        self.sm.tx().try_push(target.raw().cast_unsigned())
+    }
+
+    /// Top up the target queue from a fixed pitch, call this at least once per
+    /// [`TARGET_QUEUE_DEPTH`] matches.
+    pub fn refill(&mut self, targets: &mut PitchTargets) {
+        while !self.sm.tx().full() {
+            let target = targets.next().expect("Pitch targets never end");
+            self.sm.tx().push(target.raw().cast_unsigned());
+        }
+    }
+
+    /// Number of targets waiting behind the armed one.
+    pub fn queued(&mut self) -> usize {
+        self.sm.tx().level().into()
+    }
+
+    /// Wait for the next match.
+    pub async fn wait_for_match(&mut self) {
+        self.irq.wait().await;
+    }
+
+    pub fn ticks(&mut self) -> Step {
+        let rx = self.sm.rx();
+
+        //Purging buffer of stale data
+        let num_stale_data = rx.level();
+        for _ in 0..num_stale_data {
+            rx.try_pull();
+        }
+        //NOTE: a new value is pushed into rx in at most 12 clock cycles, 36 on a match.
+        Step::new(embassy_futures::block_on(rx.wait_pull()).cast_signed())
+    }
+
+    /// Load `target` into X without losing the encoder state.
+    ///
+    /// Must only be called while the state machine is stopped and the TX FIFO is empty.
+    /// The sequence is shared with the host model, see [`park`].
+    fn load_target(&mut self, target: Step) {
+        // The program is loaded at address 0, so addresses need no offset.
+        let pc = self.sm.get_addr();
+        for instr in park(pc) {
+            self.exec(*instr);
+        }
+        self.sm.tx().push(target.raw().cast_unsigned());
+        for instr in LOAD {
+            self.exec(instr);
+        }
+    }
+
+    fn exec(&mut self, instr: LoadInstruction) {
+        let operands = match instr {
+            LoadInstruction::Push => InstructionOperands::PUSH {
+                if_full: false,
+                block: false,
+            },
+            LoadInstruction::OutIsr => InstructionOperands::OUT {
+                destination: OutDestination::ISR,
+                bit_count: 2,
+            },
+            LoadInstruction::InPins => InstructionOperands::IN {
+                source: InSource::PINS,
+                bit_count: 2,
+            },
+            LoadInstruction::MovOsrIsr | LoadInstruction::EndPulse => {
+                mov(MovDestination::OSR, MovSource::ISR)
+            }
+            LoadInstruction::Pull => InstructionOperands::PULL {
+                if_empty: false,
+                block: false,
+            },
+            LoadInstruction::MovXOsr => mov(MovDestination::X, MovSource::OSR),
+            LoadInstruction::Jmp(address) => InstructionOperands::JMP {
+                condition: JmpCondition::Always,
+                address,
+            },
+        };
+        let side_set = (instr == LoadInstruction::EndPulse).then_some(0);
+        let encoded = Instruction {
+            operands,
+            delay: 0,
+            side_set,
+        }
+        .encode(self.side_set);
+        // SAFETY: The state machine is stopped and every instruction used
+        // leaves the registers in a state the program expects.
+        unsafe { self.sm.exec_instr(encoded) }
+    }
+}
+
+fn mov(destination: MovDestination, source: MovSource) -> InstructionOperands {
+    InstructionOperands::MOV {
+        destination,
+        op: MovOperation::None,
+        source,
+    }
+}
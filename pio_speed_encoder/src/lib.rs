@@ -1,5 +1,15 @@
 #![no_std]
 
+pub mod compare;
+#[cfg(feature = "embedded-hal")]
+mod qei;
+pub mod sampler;
+#[cfg(feature = "embassy-sync")]
+pub mod shared;
 pub mod step_verstion;
 pub mod substep_version;
-pub use pio_speed_encoder_logic::{Encoder, Speed, Step, SubStep};
+mod wiring;
+pub use pio_speed_encoder_logic::{
+    DriftMonitor, Encoder, EncoderExt, EncoderReader, PullTest, Snapshot, Speed, Step, SubStep,
+    WiringFault, WiringMonitor,
+};
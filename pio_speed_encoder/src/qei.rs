@@ -0,0 +1,38 @@
+//! embedded-hal 0.2 [`Qei`] implementations.
+//!
+//! Both report the state as of the last [`Encoder::update`], since `Qei` only takes `&self`.
+use embassy_rp::pio::Instance;
+use embedded_hal::Qei;
+use pio_speed_encoder_logic::{Direction, Encoder};
+
+use crate::{step_verstion, substep_version};
+
+/// Counting up is counterclockwise, matching [`Step`](crate::Step).
+fn qei_direction(direction: Direction) -> embedded_hal::Direction {
+    match direction {
+        Direction::CounterClockwise => embedded_hal::Direction::Upcounting,
+        Direction::Clockwise => embedded_hal::Direction::Downcounting,
+    }
+}
+
+impl<T: Instance, const SM: usize, const IDLE_STOPING_TIME_MS: u64> Qei
+    for substep_version::PioEncoder<'_, T, SM, IDLE_STOPING_TIME_MS>
+{
+    type Count = i32;
+    fn count(&self) -> i32 {
+        Encoder::ticks(self).raw()
+    }
+    fn direction(&self) -> embedded_hal::Direction {
+        qei_direction(self.snapshot().direction)
+    }
+}
+
+impl<T: Instance, const SM: usize> Qei for step_verstion::PioEncoder<'_, T, SM> {
+    type Count = i32;
+    fn count(&self) -> i32 {
+        Encoder::ticks(self).raw()
+    }
+    fn direction(&self) -> embedded_hal::Direction {
+        qei_direction(step_verstion::PioEncoder::direction(self))
+    }
+}
@@ -0,0 +1,81 @@
+//! Drive an [`Encoder`] at a fixed rate.
+use embassy_time::{Duration, Instant, Ticker};
+use pio_speed_encoder_logic::{Encoder, JitterStats, check_period};
+
+/// Updates an encoder on every tick of an [`embassy_time::Ticker`] and keeps [`JitterStats`].
+pub struct Sampler<E: Encoder> {
+    encoder: E,
+    ticker: Ticker,
+    stats: JitterStats,
+    idle_timeout: Option<Duration>,
+    warned: bool,
+}
+
+impl<E: Encoder> Sampler<E> {
+    /// Logs a warning if `period` is too slow for the encoder to stay accurate.
+    pub fn new(encoder: E, period: Duration) -> Self {
+        let sampler = Self {
+            encoder,
+            ticker: Ticker::every(period),
+            stats: JitterStats::new(period),
+            idle_timeout: None,
+            warned: false,
+        };
+        sampler.warn_if_slow(period);
+        sampler
+    }
+    /// Also check the rate against the encoder's idle stopping time.
+    #[must_use]
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self.warn_if_slow(self.stats.period());
+        self
+    }
+
+    fn warn_if_slow(&self, interval: Duration) {
+        if let Some(warning) = check_period(interval, self.idle_timeout) {
+            defmt::warn!(
+                "encoder updated every {}us: {}",
+                interval.as_micros(),
+                warning
+            );
+        }
+    }
+
+    /// Wait for the next tick and update the encoder.
+    pub async fn sample(&mut self) -> &mut E {
+        self.ticker.next().await;
+        self.encoder.update();
+        self.stats.record(Instant::now());
+        // Only warn the first time the loop falls behind, to avoid flooding the log.
+        if !self.warned && self.stats.rate_warning(self.idle_timeout).is_some() {
+            self.warned = true;
+            self.warn_if_slow(self.stats.longest_interval());
+        }
+        &mut self.encoder
+    }
+    /// Sample forever, calling `on_sample` after every update.
+    pub async fn run(&mut self, mut on_sample: impl FnMut(&mut E)) -> ! {
+        loop {
+            on_sample(self.sample().await);
+        }
+    }
+
+    pub fn stats(&self) -> &JitterStats {
+        &self.stats
+    }
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+        self.warned = false;
+    }
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+    pub fn encoder_mut(&mut self) -> &mut E {
+        &mut self.encoder
+    }
+    /// Release the wrapped encoder.
+    pub fn into_inner(self) -> E {
+        self.encoder
+    }
+}
@@ -0,0 +1,92 @@
+//! Share one encoder between several embassy tasks.
+//!
+//! A sampling task owns the [`PioEncoder`] and publishes a [`Snapshot`] to a [`Watch`]
+//! every period. Other tasks read the latest snapshot through an [`EncoderHandle`].
+//!
+//! ```ignore
+//! static ENCODER: SnapshotWatch<CriticalSectionRawMutex, 4> = Watch::new();
+//!
+//! #[embassy_executor::task]
+//! async fn sample(encoder: PioEncoder<'static, PIO0, 0, 30>) -> ! {
+//!     publish(encoder, &ENCODER, Duration::from_millis(1)).await
+//! }
+//!
+//! // In any other task
+//! let speed = EncoderHandle::new(&ENCODER).speed();
+//! ```
+use embassy_rp::pio::Instance;
+use embassy_sync::{blocking_mutex::raw::RawMutex, watch::Watch};
+use embassy_time::{Duration, Ticker};
+use pio_speed_encoder_logic::{EncoderReader, Snapshot, Speed, Step, SubStep};
+
+use crate::substep_version::PioEncoder;
+
+/// Holds the latest snapshot, `N` is the number of receivers that can wait for changes.
+pub type SnapshotWatch<M, const N: usize> = Watch<M, Snapshot, N>;
+
+/// Update `encoder` every `period` and publish the result to `watch`.
+///
+/// The first snapshot is published before the first wait.
+pub async fn publish<
+    'd,
+    T: Instance,
+    const SM: usize,
+    const IDLE_STOPING_TIME_MS: u64,
+    M,
+    const N: usize,
+>(
+    mut encoder: PioEncoder<'d, T, SM, IDLE_STOPING_TIME_MS>,
+    watch: &SnapshotWatch<M, N>,
+    period: Duration,
+) -> !
+where
+    M: RawMutex,
+{
+    let sender = watch.sender();
+    let mut ticker = Ticker::every(period);
+    loop {
+        sender.send(encoder.update_snapshot());
+        ticker.next().await;
+    }
+}
+
+/// Cheap read-only view of an encoder owned by another task.
+///
+/// Tasks that need to wait for new readings can take a receiver from the watch instead.
+///
+/// Reads as stopped at step zero until the first snapshot is published.
+pub struct EncoderHandle<'a, M: RawMutex, const N: usize> {
+    watch: &'a SnapshotWatch<M, N>,
+}
+
+impl<M: RawMutex, const N: usize> Clone for EncoderHandle<'_, M, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<M: RawMutex, const N: usize> Copy for EncoderHandle<'_, M, N> {}
+
+impl<'a, M: RawMutex, const N: usize> EncoderHandle<'a, M, N> {
+    pub fn new(watch: &'a SnapshotWatch<M, N>) -> Self {
+        Self { watch }
+    }
+    /// The latest published snapshot, if any.
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        self.watch.try_get()
+    }
+}
+
+impl<M: RawMutex, const N: usize> EncoderReader for EncoderHandle<'_, M, N> {
+    fn speed(&self) -> Speed {
+        self.snapshot()
+            .map_or(Speed::stopped(), |snapshot| snapshot.speed)
+    }
+    fn position(&self) -> SubStep {
+        self.snapshot()
+            .map_or(SubStep::new(0), |snapshot| snapshot.position)
+    }
+    fn ticks(&self) -> Step {
+        self.snapshot()
+            .map_or(Step::new(0), |snapshot| snapshot.step)
+    }
+}
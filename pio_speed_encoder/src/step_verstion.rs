@@ -1,16 +1,33 @@
-use embassy_futures::yield_now;
 use embassy_rp::{
     Peri,
     gpio::Pull,
     pio::{
-        Common, Config, Direction, FifoJoin, Instance, LoadedProgram, PioPin, ShiftDirection,
+        Common, Config, Direction, FifoJoin, Instance, LoadedProgram, Pin, PioPin, ShiftDirection,
         StateMachine,
+        program::{Instruction, InstructionOperands, OutDestination, SetDestination},
     },
 };
+use embassy_time::{Duration, Instant, Timer};
+use pio_speed_encoder_logic::{
+    CountDirection, Encoder, PackedCount, PioTiming, Speed, Step, SubStep, UpdateError,
+    WindowSpeed, WiringFault,
+};
 pub struct PioEncoderProgram<'a, PIO: Instance> {
     prg: LoadedProgram<'a, PIO>,
+    filter_loops: u8,
+    filter_loop_cycles: u16,
+    counts_errors: bool,
 }
 
+/// The filter length is the 5 bit immediate of a `SET` instruction.
+const MAX_FILTER_LOOPS: u16 = 31;
+/// Cycles taken by a loop waiting on the filter, without the delay on `hold`.
+const FILTER_LOOP_CYCLES: u16 = 10;
+/// Longest delay an instruction can have when the program uses no side set.
+const MAX_HOLD_DELAY: u16 = 31;
+/// Longest pulse the filtered program can drop, about 10µs at 125MHz.
+pub const MAX_FILTER_CYCLES: u16 = MAX_FILTER_LOOPS * (FILTER_LOOP_CYCLES + MAX_HOLD_DELAY);
+
 impl<'a, PIO: Instance> PioEncoderProgram<'a, PIO> {
     /// Load the program into the given pio
     pub fn new(common: &mut Common<'a, PIO>) -> Self {
@@ -18,24 +35,135 @@ impl<'a, PIO: Instance> PioEncoderProgram<'a, PIO> {
 
         let prg = common.load_program(&prg.program);
 
-        Self { prg }
+        Self {
+            prg,
+            filter_loops: 0,
+            filter_loop_cycles: FILTER_LOOP_CYCLES,
+            counts_errors: false,
+        }
+    }
+    /// Load the variant of the program that counts invalid transitions into the given pio.
+    ///
+    /// Encoders using it report [`Encoder::error_count`].
+    /// The count is packed next to a 24 bit step count, so [`Encoder::update`] must be called
+    /// before the encoder moves 2^23 steps or 256 invalid transitions happen.
+    /// A loop takes up to 11 PIO cycles instead of 10, which lowers the fastest step rate to match.
+    pub fn new_counting(common: &mut Common<'a, PIO>) -> Self {
+        let prg = embassy_rp::pio::program::pio_file!("src/quadrature_encoder_counting.pio");
+
+        let prg = common.load_program(&prg.program);
+
+        Self {
+            prg,
+            filter_loops: 0,
+            filter_loop_cycles: FILTER_LOOP_CYCLES,
+            counts_errors: true,
+        }
+    }
+    /// Load the glitch filtered variant of the program into the given pio.
+    ///
+    /// A pin change is only counted once it has been stable for at least `min_pulse_cycles`
+    /// PIO cycles, rounded up to a whole number of filter loops, see [`Self::filter_cycles`].
+    /// Steps are counted that much later, and the fastest step rate drops to match.
+    /// Every encoder using the program shares the filter length.
+    ///
+    /// The filter can drop pulses up to [`MAX_FILTER_CYCLES`] long, about 10µs at 125MHz.
+    /// Longer noise, e.g. from VFD motor cables, needs an RC filter on the inputs as well.
+    ///
+    /// # Panics
+    /// If `min_pulse_cycles` is more than [`MAX_FILTER_CYCLES`].
+    pub fn new_filtered(common: &mut Common<'a, PIO>, min_pulse_cycles: u16) -> Self {
+        assert!(
+            min_pulse_cycles <= MAX_FILTER_CYCLES,
+            "The filter is too long for the PIO program"
+        );
+        // Use the shortest loop that fits the filter in 31 loops, for the finest steps.
+        let filter_loop_cycles = min_pulse_cycles
+            .div_ceil(MAX_FILTER_LOOPS)
+            .max(FILTER_LOOP_CYCLES);
+        let filter_loops = min_pulse_cycles.div_ceil(filter_loop_cycles);
+        let filter_loops = u8::try_from(filter_loops).expect("At most 31 loops");
+        let delay = u8::try_from(filter_loop_cycles - FILTER_LOOP_CYCLES).expect("At most 31");
+
+        let mut prg = embassy_rp::pio::program::pio_file!("src/quadrature_encoder_filtered.pio");
+        prg.program.code[prg.public_defines.reset as usize] = set_x(filter_loops);
+        prg.program.code[prg.public_defines.hold as usize] = Instruction {
+            operands: InstructionOperands::OUT {
+                destination: OutDestination::NULL,
+                bit_count: 2,
+            },
+            delay,
+            side_set: None,
+        }
+        .encode(prg.program.side_set);
+
+        let prg = common.load_program(&prg.program);
+
+        Self {
+            prg,
+            filter_loops,
+            filter_loop_cycles,
+            counts_errors: false,
+        }
+    }
+    /// How many loops a pin change must be stable for, 0 for the unfiltered program.
+    pub fn filter_loops(&self) -> u8 {
+        self.filter_loops
+    }
+    /// How many PIO cycles a pin change must be stable for, 0 for the unfiltered program.
+    pub fn filter_cycles(&self) -> u16 {
+        u16::from(self.filter_loops) * self.filter_loop_cycles
+    }
+    /// The system clock timing of the program, including its filter delay.
+    pub fn timing(&self) -> PioTiming {
+        crate::substep_version::sys_timing()
+            .with_filter_loops(self.filter_loops.into(), self.filter_loop_cycles.into())
+    }
+    /// Whether this is the program loaded by [`Self::new_counting`].
+    pub fn counts_errors(&self) -> bool {
+        self.counts_errors
     }
 }
 
+fn set_x(data: u8) -> u16 {
+    InstructionOperands::SET {
+        destination: SetDestination::X,
+        data,
+    }
+    .encode()
+}
+
 /// Pio Backed quadrature encoder reader
+///
+/// Uses a smaller PIO program than the sub-step version, at the cost of step resolution
+/// and a coarser speed estimate.
 pub struct PioEncoder<'d, T: Instance, const SM: usize> {
     sm: StateMachine<'d, T, SM>,
+    pin_a: Pin<'d, T>,
+    pin_b: Pin<'d, T>,
+    count_direction: CountDirection,
+    poll_interval: Duration,
+    speed: WindowSpeed,
+    /// When the pins behind the count were last sampled, see [`Encoder::sample_instant`].
+    sampled: Option<Instant>,
+    /// How long after a pin change the filter lets it through.
+    filter_delay: Duration,
+    /// Full counts unpacked from the counting program, `None` for the other programs.
+    packed: Option<(Step, u32)>,
 }
 
 impl<'d, T: Instance, const SM: usize> PioEncoder<'d, T, SM> {
     /// Configure a state machine with the loaded [PioEncoderProgram]
+    ///
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine does not start pushing readings.
     pub fn new(
         pio: &mut Common<'d, T>,
         mut sm: StateMachine<'d, T, SM>,
         pin_a: Peri<'d, impl PioPin + 'd>,
         pin_b: Peri<'d, impl PioPin + 'd>,
         program: &PioEncoderProgram<'d, T>,
-    ) -> Self {
+    ) -> Result<Self, UpdateError> {
         let mut pin_a = pio.make_pio_pin(pin_a);
         let mut pin_b = pio.make_pio_pin(pin_b);
         pin_a.set_pull(Pull::Up);
@@ -49,35 +177,212 @@ impl<'d, T: Instance, const SM: usize> PioEncoder<'d, T, SM> {
 
         cfg.use_program(&program.prg, &[]);
         sm.set_config(&cfg);
+        // Arm the filter before the first change, the unfiltered program ignores X
+        // and the counting program starts counting errors from zero.
+        // SAFETY: the state machine is disabled and X is a scratch register.
+        unsafe {
+            sm.exec_instr(set_x(program.filter_loops));
+        }
         sm.set_enable(true);
-        Self { sm }
+        let mut encoder = Self {
+            sm,
+            pin_a,
+            pin_b,
+            count_direction: CountDirection::Normal,
+            poll_interval: Duration::from_millis(1),
+            speed: WindowSpeed::new(Duration::from_millis(100), Step::new(0), Instant::now()),
+            sampled: None,
+            filter_delay: program.timing().filter_delay(),
+            packed: program.counts_errors.then_some((Step::new(0), 0)),
+        };
+        encoder.restart_speed_window(Duration::from_millis(100))?;
+        Ok(encoder)
+    }
+
+    fn restart_speed_window(&mut self, window: Duration) -> Result<(), UpdateError> {
+        let step = Step::new(self.read_ticks()?);
+        self.speed = WindowSpeed::new(window, step, self.count_instant());
+        Ok(())
     }
 
-    pub fn ticks(&mut self) -> i32 {
-        let rx = self.sm.rx();
+    /// When the pins behind a count read now were sampled.
+    ///
+    /// The filter only counts a step once it has held for the filter delay,
+    /// so the count is as of that long ago.
+    fn count_instant(&self) -> Instant {
+        let now = Instant::now();
+        now.checked_sub(self.filter_delay).unwrap_or(now)
+    }
+
+    /// How long [`Encoder::speed`] counts steps for, defaults to 100ms.
+    ///
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the count could not be read to start the window.
+    /// # Panics
+    /// If `window` is zero.
+    pub fn with_speed_window(mut self, window: Duration) -> Result<Self, UpdateError> {
+        self.restart_speed_window(window)?;
+        Ok(self)
+    }
+
+    /// How long the async waits sleep between samples, defaults to 1ms.
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Count in the given direction, e.g. when the encoder is mounted the other way round.
+    ///
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the count could not be read to restart the speed window.
+    pub fn with_count_direction(
+        mut self,
+        count_direction: CountDirection,
+    ) -> Result<Self, UpdateError> {
+        self.count_direction = count_direction;
+        self.restart_speed_window(self.speed.window())?;
+        Ok(self)
+    }
 
-        //Purging buffer of stale data
-        let num_stale_data = rx.level();
-        for _ in 0..num_stale_data {
-            rx.try_pull();
+    /// Direction of the most recent step seen by [`Encoder::update`].
+    pub fn direction(&self) -> pio_speed_encoder_logic::Direction {
+        self.speed.direction()
+    }
+    /// Read the current count straight from the state machine.
+    ///
+    /// Unlike [`Encoder::ticks`] this does not need an [`Encoder::update`] first.
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine is stalled or disabled.
+    pub fn read_ticks(&mut self) -> Result<i32, UpdateError> {
+        let raw = read_raw(&mut self.sm)?;
+        let step = match &mut self.packed {
+            Some((step, errors)) => {
+                let packed = PackedCount::new(raw.cast_unsigned());
+                *step = packed.extend_step(*step);
+                *errors = packed.extend_errors(*errors);
+                *step
+            }
+            None => Step::new(raw),
+        };
+        Ok(self.count_direction.apply_step(step).raw())
+    }
+    /// Check for floating inputs by briefly switching each pull up to a pull down.
+    ///
+    /// The encoder must be stationary, and must not have open collector outputs,
+    /// see [`PullTest`](crate::PullTest).
+    /// Unlike the sub-step version the count does not line up with the phases,
+    /// so a [`WiringMonitor`](crate::WiringMonitor) can only tell that the encoder is disconnected.
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine is stalled or disabled.
+    pub async fn check_wiring(&mut self) -> Result<Option<WiringFault>, UpdateError> {
+        let Self {
+            sm,
+            pin_a,
+            pin_b,
+            packed,
+            ..
+        } = self;
+        let packed = packed.is_some();
+        let test = crate::wiring::pull_test(pin_a, pin_b, || {
+            let raw = read_raw(sm)?;
+            Ok(if packed {
+                PackedCount::new(raw.cast_unsigned()).step()
+            } else {
+                Step::new(raw)
+            })
+        })
+        .await?;
+        Ok(test.fault())
+    }
+    /// Wait for the count to change and return the new count.
+    ///
+    /// The PIO program pushes the count every loop, so there is no interrupt that only
+    /// fires on a step. Instead the task sleeps for the poll interval between samples.
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine is stalled or disabled.
+    pub async fn wait_for_step(&mut self) -> Result<i32, UpdateError> {
+        let current = self.read_ticks()?;
+        loop {
+            let ticks = self.read_ticks()?;
+            if ticks != current {
+                return Ok(ticks);
+            }
+            Timer::after(self.poll_interval).await;
         }
-        //NOTE: Note a new value is pushed into rx in at most 13 clock cycles.
-        // At 125Mhz this is about 0.1 micro second.
-        embassy_futures::block_on(rx.wait_pull()) as i32
     }
-    pub async fn read(&mut self) -> embassy_rp::pio_programs::rotary_encoder::Direction {
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine is stalled or disabled.
+    pub async fn read(
+        &mut self,
+    ) -> Result<embassy_rp::pio_programs::rotary_encoder::Direction, UpdateError> {
         use embassy_rp::pio_programs::rotary_encoder::Direction;
-        let current = self.ticks();
+        let current = self.read_ticks()?;
+        Ok(match current.cmp(&self.wait_for_step().await?) {
+            core::cmp::Ordering::Greater => Direction::CounterClockwise,
+            core::cmp::Ordering::Less | core::cmp::Ordering::Equal => Direction::Clockwise,
+        })
+    }
+}
 
-        loop {
-            return match current.cmp(&self.ticks()) {
-                core::cmp::Ordering::Less => Direction::Clockwise,
-                core::cmp::Ordering::Greater => Direction::CounterClockwise,
-                core::cmp::Ordering::Equal => {
-                    yield_now().await;
-                    continue;
-                }
-            };
+/// How long to wait for a reading.
+///
+/// A loop takes at most 11 cycles, or 41 per filter loop, well under a micro second.
+const PULL_TIMEOUT: Duration = Duration::from_micros(20);
+
+fn read_raw<T: Instance, const SM: usize>(
+    sm: &mut StateMachine<'_, T, SM>,
+) -> Result<i32, UpdateError> {
+    let rx = sm.rx();
+
+    //Purging buffer of stale data
+    let num_stale_data = rx.level();
+    for _ in 0..num_stale_data {
+        rx.try_pull();
+    }
+    //NOTE: Note a new value is pushed into rx in at most 13 clock cycles.
+    // At 125Mhz this is about 0.1 micro second.
+    let word = crate::substep_version::pull_before(rx, Instant::now() + PULL_TIMEOUT)?;
+    Ok(word as i32)
+}
+
+impl<'d, T: Instance, const SM: usize> Encoder for PioEncoder<'d, T, SM> {
+    /// A failed read keeps the previous count, see [`Encoder::try_update`].
+    fn update(&mut self) {
+        if let Err(error) = self.try_update() {
+            defmt::warn!("Encoder reading failed: {}", error);
         }
     }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        let step = Step::new(self.read_ticks()?);
+        let sampled = self.count_instant();
+        self.speed.update(step, sampled);
+        self.sampled = Some(sampled);
+        Ok(())
+    }
+    /// Average over the speed window, see [`PioEncoder::with_speed_window`].
+    fn speed(&self) -> Speed {
+        self.speed.speed()
+    }
+    /// Only has step resolution.
+    fn position(&self) -> SubStep {
+        self.speed.position()
+    }
+    /// With [`PioEncoderProgram::new_counting`] the PIO only reports the low 24 bits of the count,
+    /// which wrap every 2^24 steps (at ±2^23 from zero). [`Encoder::update`] unwraps them into
+    /// the full count, but only if the encoder moved less than 2^23 steps since the previous
+    /// read, any further and the count is off by a multiple of 2^24.
+    fn ticks(&self) -> Step {
+        self.speed.steps()
+    }
+    /// As of the last update, `None` unless the program was loaded with
+    /// [`PioEncoderProgram::new_counting`].
+    fn error_count(&self) -> Option<u32> {
+        self.packed.map(|(_, errors)| errors)
+    }
+    /// `None` until the first update, moved back by the filter delay with
+    /// [`PioEncoderProgram::new_filtered`].
+    fn sample_instant(&self) -> Option<Instant> {
+        self.sampled
+    }
 }
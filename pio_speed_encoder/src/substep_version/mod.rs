@@ -3,45 +3,269 @@ use embassy_rp::{
     Peri,
     pio::{Common, Instance, PioPin, StateMachine},
 };
+use embassy_time::{Duration, Instant, Timer};
 /// Contains logic for parsing the pio messages into logical values
 mod pio;
+/// Position capture on an external probe input
+mod probe;
 
 use pio::EncoderStateMachine;
 pub use pio::PioEncoderProgram;
-use pio_speed_encoder_logic::{Encoder, EncoderState, Speed, Step, SubStep};
+pub(crate) use pio::{pull_before, sys_timing};
+use pio_speed_encoder_logic::{
+    ClockDivider, CountDirection, Direction, DirectionDetector, DriftMonitor, Encoder,
+    EncoderState, PioTiming, Snapshot, Speed, Step, SubStep, UpdateError, WaitFor, WiringFault,
+    WiringMonitor, ZoneEvents, ZoneMonitor,
+};
+pub use probe::{PioProbe, PioProbeProgram, ProbeEdge};
 type CalibrationData = [u32; 4];
 
 /// Pio Backed quadrature encoder reader
 pub struct PioEncoder<'d, T: Instance, const SM: usize, const IDLE_STOPING_TIME_MS: u64> {
     sm: EncoderStateMachine<'d, T, SM>,
     state: EncoderState<IDLE_STOPING_TIME_MS>,
+    poll_interval: Duration,
+    drift: DriftMonitor,
+    rejected: u32,
+    /// Why the most recent update was rejected, `None` once an update succeeds.
+    failing: Option<UpdateError>,
 }
 
 impl<'d, T: Instance, const SM: usize, const IDLE_STOPING_TIME_MS: u64>
     PioEncoder<'d, T, SM, IDLE_STOPING_TIME_MS>
 {
+    /// Configure a state machine with the loaded [`PioEncoderProgram`] and take the first reading.
+    ///
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine does not push a reading,
+    /// [`UpdateError::Desynchronized`] if the first reading does not match the pins the
+    /// counter was seeded from, see [`check_sync`](pio_speed_encoder_logic::check_sync).
     pub fn new(
         pio: &mut Common<'d, T>,
         sm: StateMachine<'d, T, SM>,
         pin_a: Peri<'d, impl PioPin + 'd>,
         pin_b: Peri<'d, impl PioPin + 'd>,
         program: &PioEncoderProgram<'d, T>,
-    ) -> Self {
-        let mut sm = EncoderStateMachine::new(pio, sm, pin_a, pin_b, program);
-        let inial_data = sm.pull_data();
-        Self {
-            sm: sm,
-            state: EncoderState::new(inial_data),
+    ) -> Result<Self, UpdateError> {
+        Self::with_clock_divider(pio, sm, pin_a, pin_b, program, ClockDivider::NONE)
+    }
+
+    /// Same as [`Self::new`], with the state machine running slower than the system clock.
+    ///
+    /// This lengthens the time before the transition timer overflows, at the cost of
+    /// time resolution and the maximum step rate, see [`PioTiming::tradeoffs`].
+    /// Above a divider of about 3000 at 125MHz every read times out.
+    ///
+    /// # Errors
+    /// The same as [`Self::new`].
+    pub fn with_clock_divider(
+        pio: &mut Common<'d, T>,
+        sm: StateMachine<'d, T, SM>,
+        pin_a: Peri<'d, impl PioPin + 'd>,
+        pin_b: Peri<'d, impl PioPin + 'd>,
+        program: &PioEncoderProgram<'d, T>,
+        divider: ClockDivider,
+    ) -> Result<Self, UpdateError> {
+        let mut sm = EncoderStateMachine::new(pio, sm, pin_a, pin_b, program, divider);
+        let inial_data = sm.pull_data()?;
+        if let Err(error) = sm.check_sync(inial_data.step) {
+            defmt::warn!(
+                "Encoder state machine started out of phase, expected step {} but read {}",
+                error.expected,
+                error.read
+            );
+            return Err(UpdateError::Desynchronized);
+        }
+        let state =
+            EncoderState::new(inial_data).with_timer_wrap(sm.timing().tradeoffs().overflow_window);
+        Ok(Self {
+            sm,
+            state,
+            poll_interval: Duration::from_millis(1),
+            drift: DriftMonitor::new(),
+            rejected: 0,
+            failing: None,
+        })
+    }
+
+    /// How long the async waits sleep between samples, defaults to 1ms.
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Reject readings that move more than `max_step_jump` steps between updates,
+    /// see [`Encoder::try_update`].
+    #[must_use]
+    pub fn with_max_step_jump(mut self, max_step_jump: u32) -> Self {
+        self.state = self.state.with_max_step_jump(max_step_jump);
+        self
+    }
+
+    pub fn timing(&self) -> PioTiming {
+        self.sm.timing()
+    }
+
+    /// Tell the encoder the system clock changed.
+    ///
+    /// Changes made through embassy are picked up on the next update,
+    /// this is only needed when the clock registers are written directly.
+    pub fn set_clock_hz(&mut self, clock_hz: u32) {
+        self.sm.set_clock_hz(clock_hz);
+        self.drift.reset();
+        self.state = self
+            .state
+            .clone()
+            .with_timer_wrap(self.sm.timing().tradeoffs().overflow_window);
+    }
+    /// How the PIO time base compares to embassy time, see [`DriftMonitor`].
+    pub fn drift(&self) -> &DriftMonitor {
+        &self.drift
+    }
+    /// Adjust the assumed clock so PIO time matches embassy time.
+    ///
+    /// Returns false, changing nothing, until the drift has been observed for long enough.
+    pub fn compensate_drift(&mut self) -> bool {
+        match self.drift.corrected(&self.sm.timing()) {
+            Some(timing) => {
+                self.set_clock_hz(timing.clock_hz());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Count in the given direction, e.g. when the encoder is mounted the other way round.
+    ///
+    /// The current position is re-read, so any sub-step estimate is reset.
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine does not push a reading.
+    pub fn with_count_direction(
+        mut self,
+        count_direction: CountDirection,
+    ) -> Result<Self, UpdateError> {
+        self.restart(count_direction)?;
+        Ok(self)
+    }
+
+    /// Start estimating from a fresh reading, keeping the settings.
+    fn restart(&mut self, count_direction: CountDirection) -> Result<(), UpdateError> {
+        let measurement = self.sm.pull_data()?;
+        self.state = EncoderState::with_count_direction(measurement, count_direction)
+            .with_max_step_jump(self.state.max_step_jump())
+            .with_timer_wrap(self.sm.timing().tradeoffs().overflow_window);
+        Ok(())
+    }
+
+    /// Number of readings [`Encoder::update`] has dropped, saturates at `u32::MAX`.
+    pub fn rejected_readings(&self) -> u32 {
+        self.rejected
+    }
+
+    /// Start detecting the correct count direction, move the encoder in the `commanded`
+    /// direction then pass [`Encoder::ticks`] to [`DirectionDetector::suggest`].
+    pub fn direction_detector(&self, commanded: Direction) -> DirectionDetector {
+        DirectionDetector::new(commanded, self.state.count_direction(), self.state.steps())
+    }
+
+    /// Check for floating inputs by briefly switching each pull up to a pull down.
+    ///
+    /// The encoder must be stationary, and must not have open collector outputs,
+    /// see [`PullTest`](crate::PullTest).
+    /// Any steps counted during the test are undone once the pulls are restored.
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine is stalled or disabled.
+    pub async fn check_wiring(&mut self) -> Result<Option<WiringFault>, UpdateError> {
+        let test = self.sm.pull_test().await?;
+        // The false transitions restarted the transition timer.
+        self.drift.reset();
+        Ok(test.fault())
+    }
+    /// Start watching for a stuck or disconnected wire, command a move then
+    /// [`WiringMonitor::record`] the ticks after each update and ask for the [`WiringMonitor::fault`].
+    pub fn wiring_monitor(&self) -> WiringMonitor {
+        WiringMonitor::new(self.state.steps())
+    }
+
+    /// Update the encoder until `condition` is met.
+    ///
+    /// The PIO program pushes a fresh sample every loop, so there is no interrupt that only
+    /// fires on a change. Instead the task sleeps for the poll interval between samples.
+    pub async fn wait_for(&mut self, condition: WaitFor) {
+        loop {
+            self.update();
+            if condition.is_met(&self.state) {
+                return;
+            }
+            Timer::after(self.poll_interval).await;
         }
     }
+    /// Wait for the step count to change and return the new count.
+    pub async fn wait_for_step(&mut self) -> Step {
+        self.wait_for(WaitFor::next_step(&self.state)).await;
+        self.state.steps()
+    }
+    /// Wait for the unwrapped position to reach or cross `target`,
+    /// see [`EncoderState::unwrapped_position`].
+    pub async fn wait_for_position(&mut self, target: i64) {
+        self.wait_for(WaitFor::position(&self.state, target)).await;
+    }
+    /// Wait for the magnitude of the speed to exceed `threshold`.
+    pub async fn wait_for_speed_above(&mut self, threshold: Speed) {
+        self.wait_for(WaitFor::speed_above(threshold)).await;
+    }
+    /// Wait for the encoder to come to rest.
+    pub async fn wait_until_stopped(&mut self) {
+        self.wait_for(WaitFor::stopped()).await;
+    }
+
+    /// Everything known about the encoder, taken from a single measurement.
+    pub fn snapshot(&self) -> Snapshot {
+        self.state.snapshot()
+    }
+    /// Update the encoder and return the new [`Snapshot`].
+    pub fn update_snapshot(&mut self) -> Snapshot {
+        self.update();
+        self.snapshot()
+    }
+
+    /// Update the encoder and check the new position against soft limits and zones.
+    pub fn update_and_monitor<const N: usize>(
+        &mut self,
+        monitor: &mut ZoneMonitor<N>,
+    ) -> ZoneEvents<N> {
+        self.update();
+        monitor.update_state(&self.state)
+    }
 }
 
 impl<'d, T: Instance, const SM: usize, const IDLE_STOPING_TIME_MS: u64> Encoder
     for PioEncoder<'d, T, SM, IDLE_STOPING_TIME_MS>
 {
+    /// Rejected readings are dropped, use [`Encoder::try_update`] to find out why.
+    ///
+    /// They are counted in [`PioEncoder::rejected_readings`], and a warning is logged
+    /// whenever the reason changes.
     fn update(&mut self) {
-        let measurement = self.sm.pull_data();
-        self.state.update(measurement);
+        match self.try_update() {
+            Ok(()) => self.failing = None,
+            Err(error) => {
+                self.rejected = self.rejected.saturating_add(1);
+                if self.failing != Some(error) {
+                    defmt::warn!("Encoder reading rejected: {}", error);
+                }
+                self.failing = Some(error);
+            }
+        }
+    }
+    fn try_update(&mut self) -> Result<(), UpdateError> {
+        if self.sm.track_sys_clock() {
+            self.set_clock_hz(self.sm.timing().clock_hz());
+        }
+        let measurement = self.sm.pull_data()?;
+        self.drift.record(measurement);
+        self.state.try_update(measurement)
     }
 
     fn ticks(&self) -> Step {
@@ -53,4 +277,16 @@ impl<'d, T: Instance, const SM: usize, const IDLE_STOPING_TIME_MS: u64> Encoder
     fn speed(&self) -> Speed {
         self.state.speed()
     }
+    fn sample_instant(&self) -> Option<Instant> {
+        Some(self.state.last_measurement().sample_instant)
+    }
+    /// Always `None`, the sub-step program uses all 32 PIO instructions and all four
+    /// registers (the timer, the step count, the previous pins and the jump target),
+    /// so there is no room left to count illegal transitions. Shortening the jump table
+    /// would only free instructions, a counter still needs a register.
+    /// The step version can count them, see
+    /// [`PioEncoderProgram::new_counting`](crate::step_verstion::PioEncoderProgram::new_counting).
+    fn error_count(&self) -> Option<u32> {
+        None
+    }
 }
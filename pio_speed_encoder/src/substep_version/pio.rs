@@ -1,27 +1,37 @@
 //This modal stores deals with interacting with the pio hardware.
 //This includes interpreting the rx output.
 //
-use embassy_futures::block_on;
 #[cfg(feature = "rp235x")]
 use embassy_rp::pio::StatusN;
 use embassy_rp::{
     Peri,
+    clocks::clk_sys_freq,
     gpio::Pull,
     pio::{
-        Common, Config, FifoJoin, Instance, LoadedProgram, PioPin, ShiftConfig, ShiftDirection,
-        StateMachine, StatusSource,
-        program::{InstructionOperands, MovDestination, MovOperation, MovSource, pio_file},
+        Common, Config, FifoJoin, Instance, LoadedProgram, Pin, PioPin, ShiftConfig,
+        ShiftDirection, StateMachine, StateMachineRx, StatusSource,
+        program::{
+            InstructionOperands, MovDestination, MovOperation, MovSource, SetDestination, pio_file,
+        },
     },
 };
-use embassy_time::Instant;
-use fixed::traits::ToFixed;
-use pio_speed_encoder_logic::{DirectionDuration, Measurement, Step};
+use embassy_time::{Duration, Instant};
+use fixed::FixedU32;
+use pio_speed_encoder_logic::{
+    ClockDivider, DirectionDuration, Measurement, PioTiming, PullTest, Step, SyncError,
+    UpdateError, check_sync, initial_step,
+};
 
 pub struct PioEncoderProgram<'a, PIO: Instance> {
     prg: LoadedProgram<'a, PIO>,
 }
 impl<'a, PIO: Instance> PioEncoderProgram<'a, PIO> {
     /// Load the program into the given pio
+    ///
+    /// There is no glitch filtered variant, a filter needs a register to count how long a
+    /// change has held and all four are in use. Use
+    /// [`step_verstion::PioEncoderProgram::new_filtered`](crate::step_verstion::PioEncoderProgram::new_filtered)
+    /// or an RC filter on the inputs.
     pub fn new(common: &mut Common<'a, PIO>) -> Self {
         let prg = pio_file!("src/quadrature_encoder_substep.pio");
         let prg = common.load_program(&prg.program);
@@ -31,17 +41,29 @@ impl<'a, PIO: Instance> PioEncoderProgram<'a, PIO> {
 
 pub struct EncoderStateMachine<'d, T: Instance, const SM: usize> {
     sm: StateMachine<'d, T, SM>,
-    clocks_per_us: u32,
+    pin_a: Pin<'d, T>,
+    pin_b: Pin<'d, T>,
+    timing: PioTiming,
+    /// The system clock `timing` was last synced to.
+    sys_hz: u32,
+    /// Pin state read when the state machine was started.
+    seed_phase: u8,
 }
 
 impl<'d, T: Instance, const SM: usize> EncoderStateMachine<'d, T, SM> {
-    /// Configure a state machine with the loaded [PioEncoderProgram]
+    /// Configure a state machine with the loaded [PioEncoderProgram],
+    /// running at the system clock slowed down by `divider`.
+    ///
+    /// See [`PioTiming::tradeoffs`] for the effect of the divider. Above a divider of about
+    /// 3000 at 125MHz a loop no longer fits in the pull timeout,
+    /// and every read fails with [`UpdateError::FifoTimeout`].
     pub fn new(
         pio: &mut Common<'d, T>,
         mut sm: StateMachine<'d, T, SM>,
         pin_a: Peri<'d, impl PioPin + 'd>,
         pin_b: Peri<'d, impl PioPin + 'd>,
         program: &PioEncoderProgram<'d, T>,
+        divider: ClockDivider,
     ) -> Self {
         use embassy_rp::pio::Direction;
         let mut pin_a = pio.make_pio_pin(pin_a);
@@ -63,7 +85,7 @@ impl<'d, T: Instance, const SM: usize> EncoderStateMachine<'d, T, SM> {
             threshold: 32,
         };
         cfg.fifo_join = FifoJoin::Duplex;
-        cfg.clock_divider = 1.to_fixed();
+        cfg.clock_divider = FixedU32::from_bits(divider.to_bits());
 
         cfg.status_sel = StatusSource::RxFifoLevel;
         #[cfg(feature = "rp2040")]
@@ -76,65 +98,175 @@ impl<'d, T: Instance, const SM: usize> EncoderStateMachine<'d, T, SM> {
         }
         cfg.use_program(&program.prg, &[]);
         sm.set_config(&cfg);
-        //Raw reading the pins this is fine since we already own the pins.
-        let pin_state = 0i32; //TODO actually read the value
-
-        critical_section::with(|_| {
-            unsafe {
-                sm.set_y((-pin_state) as u32);
-                sm.exec_instr(
-                    InstructionOperands::MOV {
-                        destination: MovDestination::OSR,
-                        op: MovOperation::None,
-                        source: MovSource::Y,
-                    }
-                    .encode(),
-                );
-                sm.set_y(match pin_state {
-                    0 => 0,
-                    1 => 3,
-                    2 => 1,
-                    3 => 2,
-                    _ => 0, /*unreachable*/
-                });
-            }
-        });
+        let seed_phase = init_phase(&mut sm);
 
         sm.set_enable(true);
         Self {
             sm,
-            clocks_per_us: (embassy_rp::clocks::clk_sys_freq() + 500_000) / 1_000_000,
+            pin_a,
+            pin_b,
+            timing: sys_timing().with_divider(divider),
+            sys_hz: clk_sys_freq(),
+            seed_phase,
         }
     }
 
-    fn pull_raw_data(&mut self) -> (u32, u32, Instant) {
-        let rx = self.sm.rx();
-
-        //Purging buffer of stale data
-        let num_stale_data = rx.level() / 2;
-        critical_section::with(|_| {
-            for _ in 0..num_stale_data {
-                block_on(rx.wait_pull());
-                block_on(rx.wait_pull());
-            }
-            //NOTE: Note a new value is pushed into rx in at most 13 clock cycles.
-            // At 125Mhz this is about 0.1 micro second.
-            (
-                block_on(rx.wait_pull()),
-                block_on(rx.wait_pull()),
-                Instant::now(),
-            )
+    pub fn timing(&self) -> PioTiming {
+        self.timing
+    }
+    /// How long to wait for a reading, a few loops of the state machine plus some slack.
+    ///
+    /// Capped at [`MAX_PULL_TIMEOUT`] because interrupts are disabled while waiting,
+    /// so very large clock dividers always time out.
+    fn pull_timeout(&self) -> Duration {
+        let loops = Duration::from_nanos(
+            PULL_TIMEOUT_LOOPS * u64::from(self.timing.tradeoffs().resolution_ns),
+        );
+        (loops + PULL_TIMEOUT_SLACK).min(MAX_PULL_TIMEOUT)
+    }
+    /// Briefly pull each input down to find floating wires, see [`PullTest`].
+    ///
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine is stalled or disabled.
+    pub async fn pull_test(&mut self) -> Result<PullTest, UpdateError> {
+        let timeout = self.pull_timeout();
+        let Self {
+            sm, pin_a, pin_b, ..
+        } = self;
+        crate::wiring::pull_test(pin_a, pin_b, || {
+            pull_raw_data(sm, timeout).map(|(_, step, _)| Step::new(step as i32))
         })
+        .await
+    }
+    /// Start-up self-check, `first` is the step of the first measurement.
+    pub fn check_sync(&self, first: Step) -> Result<(), SyncError> {
+        check_sync(self.seed_phase, first)
     }
-    pub fn pull_data(&mut self) -> Measurement {
-        let (dir_dur, step, now) = self.pull_raw_data();
-        let (direction, time_since_transition) =
-            DirectionDuration::new(dir_dur as i32).decode(self.clocks_per_us);
-        Measurement::new(
+    /// Decode durations assuming the system clock runs at `clock_hz`.
+    pub fn set_clock_hz(&mut self, clock_hz: u32) {
+        self.timing = self.timing.with_clock_hz(clock_hz);
+    }
+    /// Pick up a system clock change made through embassy, returns true if the clock changed.
+    pub fn track_sys_clock(&mut self) -> bool {
+        let sys_hz = clk_sys_freq();
+        if sys_hz == self.sys_hz {
+            return false;
+        }
+        self.sys_hz = sys_hz;
+        self.set_clock_hz(sys_hz);
+        true
+    }
+
+    /// Read the latest measurement.
+    ///
+    /// # Errors
+    /// [`UpdateError::FifoTimeout`] if the state machine is stalled or disabled.
+    pub fn pull_data(&mut self) -> Result<Measurement, UpdateError> {
+        let timeout = self.pull_timeout();
+        let (dir_dur, step, now) = pull_raw_data(&mut self.sm, timeout)?;
+        let (direction, nanos_since_transition) =
+            DirectionDuration::new(dir_dur as i32).decode_nanos(&self.timing);
+        Ok(Measurement::from_nanos(
             direction,
             Step::new(step as i32),
             now,
-            time_since_transition,
-        )
+            nanos_since_transition,
+        ))
+    }
+}
+
+fn pull_raw_data<T: Instance, const SM: usize>(
+    sm: &mut StateMachine<'_, T, SM>,
+    timeout: Duration,
+) -> Result<(u32, u32, Instant), UpdateError> {
+    let rx = sm.rx();
+
+    critical_section::with(|_| {
+        let deadline = Instant::now() + timeout;
+        //Purging buffer of stale data
+        let num_stale_data = rx.level() / 2;
+        for _ in 0..num_stale_data {
+            pull_before(rx, deadline)?;
+            pull_before(rx, deadline)?;
+        }
+        //NOTE: Note a new value is pushed into rx in at most 13 clock cycles.
+        // At 125Mhz this is about 0.1 micro second.
+        Ok((
+            pull_before(rx, deadline)?,
+            pull_before(rx, deadline)?,
+            Instant::now(),
+        ))
+    })
+}
+
+/// A running state machine pushes a reading every loop, waiting this many loops leaves room
+/// for the stale words to be purged first.
+const PULL_TIMEOUT_LOOPS: u64 = 3;
+/// Time taken by the purge and pulls themselves.
+const PULL_TIMEOUT_SLACK: Duration = Duration::from_micros(20);
+/// Longest time to spin waiting for the state machine, interrupts are disabled while waiting.
+///
+/// Covers clock dividers up to about 3000 at 125MHz.
+const MAX_PULL_TIMEOUT: Duration = Duration::from_millis(1);
+
+pub(crate) fn pull_before<T: Instance, const SM: usize>(
+    rx: &mut StateMachineRx<'_, T, SM>,
+    deadline: Instant,
+) -> Result<u32, UpdateError> {
+    loop {
+        if let Some(word) = rx.try_pull() {
+            return Ok(word);
+        }
+        if Instant::now() > deadline {
+            return Err(UpdateError::FifoTimeout);
+        }
     }
 }
+
+/// Seed OSR and Y from the current phase of the encoder, returns the pin state as `B << 1 | A`.
+///
+/// Matches the upstream C init: the low two bits of OSR hold the negated previous pin state
+/// and Y starts on the step that lines up with the phase, see [`initial_step`].
+/// Shared by every program based on quadrature_encoder_substep.pio.
+pub(crate) fn init_phase<T: Instance, const SM: usize>(sm: &mut StateMachine<'_, T, SM>) -> u8 {
+    let set_y = |data: u8| {
+        InstructionOperands::SET {
+            destination: SetDestination::Y,
+            data,
+        }
+        .encode()
+    };
+    critical_section::with(|_| {
+        // SAFETY: the state machine is disabled and these only touch its scratch registers,
+        // the word pushed by `get_y` is pulled straight back out.
+        unsafe {
+            // The state machine reads the pins itself, through the same IN mapping the program uses.
+            sm.exec_instr(
+                InstructionOperands::MOV {
+                    destination: MovDestination::Y,
+                    op: MovOperation::None,
+                    source: MovSource::PINS,
+                }
+                .encode(),
+            );
+            let pin_state = (sm.get_y() & 0b11) as u8;
+            // SET only has 5 bits of data, OUT only looks at the low two.
+            sm.exec_instr(set_y(!pin_state & 0b1_1111));
+            sm.exec_instr(
+                InstructionOperands::MOV {
+                    destination: MovDestination::OSR,
+                    op: MovOperation::None,
+                    source: MovSource::Y,
+                }
+                .encode(),
+            );
+            sm.exec_instr(set_y(initial_step(pin_state).raw() as u8));
+            pin_state
+        }
+    })
+}
+
+/// Timing of the sub-step program at the current system clock.
+pub(crate) fn sys_timing() -> PioTiming {
+    PioTiming::from_hz(clk_sys_freq())
+}
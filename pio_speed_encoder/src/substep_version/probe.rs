@@ -0,0 +1,180 @@
+use embassy_futures::block_on;
+#[cfg(feature = "rp235x")]
+use embassy_rp::pio::StatusN;
+use embassy_rp::{
+    Peri,
+    clocks::clk_sys_freq,
+    gpio::{Input, Pull},
+    pio::{
+        Common, Config, FifoJoin, Instance, LoadedProgram, PioPin, ShiftConfig, ShiftDirection,
+        StateMachine, StatusSource, program::pio_file,
+    },
+};
+use fixed::traits::ToFixed;
+use pio_speed_encoder_logic::{PROBE_LOOP_DURATION, PioTiming, ProbeCapture};
+
+use super::pio::{init_phase, sys_timing};
+
+pub struct PioProbeProgram<'a, PIO: Instance> {
+    prg: LoadedProgram<'a, PIO>,
+}
+impl<'a, PIO: Instance> PioProbeProgram<'a, PIO> {
+    /// Load the program into the given pio
+    pub fn new(common: &mut Common<'a, PIO>) -> Self {
+        let prg = pio_file!("src/quadrature_encoder_probe.pio");
+        let prg = common.load_program(&prg.program);
+        Self { prg }
+    }
+}
+
+/// Which probe edge triggers a capture.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProbeEdge {
+    /// The probe is active low.
+    Falling,
+    /// The probe is active high.
+    Rising,
+}
+
+/// Latches the encoder state when an external probe input becomes active.
+///
+/// The probe pin stays a regular GPIO input, the state machine reads it directly.
+pub struct PioProbe<'d, T: Instance, const SM: usize> {
+    sm: StateMachine<'d, T, SM>,
+    probe: Input<'d>,
+    timing: PioTiming,
+}
+
+impl<'d, T: Instance, const SM: usize> PioProbe<'d, T, SM> {
+    /// Configure a state machine with the loaded [`PioProbeProgram`]
+    pub fn new(
+        pio: &mut Common<'d, T>,
+        mut sm: StateMachine<'d, T, SM>,
+        pin_a: Peri<'d, impl PioPin + 'd>,
+        pin_b: Peri<'d, impl PioPin + 'd>,
+        probe: Peri<'d, impl PioPin + 'd>,
+        edge: ProbeEdge,
+        program: &PioProbeProgram<'d, T>,
+    ) -> Self {
+        use embassy_rp::pio::Direction;
+        let mut pin_a = pio.make_pio_pin(pin_a);
+        let mut pin_b = pio.make_pio_pin(pin_b);
+        pin_a.set_pull(Pull::Up);
+        pin_b.set_pull(Pull::Up);
+        sm.set_pin_dirs(Direction::In, &[&pin_a, &pin_b]);
+
+        let probe_pin = probe.pin();
+        let (pull, invert) = match edge {
+            ProbeEdge::Falling => (Pull::Up, false),
+            ProbeEdge::Rising => (Pull::Down, true),
+        };
+        let mut probe = Input::new(probe, pull);
+        // The program treats low as active.
+        probe.set_inversion(invert);
+
+        let mut cfg = Config::default();
+        cfg.set_in_pins(&[&pin_a, &pin_b]);
+        cfg.shift_in = ShiftConfig {
+            direction: ShiftDirection::Left,
+            auto_fill: true,
+            threshold: 32,
+        };
+        cfg.shift_out = ShiftConfig {
+            direction: ShiftDirection::Right,
+            auto_fill: false,
+            threshold: 32,
+        };
+        cfg.fifo_join = FifoJoin::Duplex;
+        cfg.clock_divider = 1.to_fixed();
+
+        // Capture only while the RX FIFO is empty.
+        cfg.status_sel = StatusSource::RxFifoLevel;
+        #[cfg(feature = "rp2040")]
+        {
+            cfg.status_n = 1;
+        }
+        #[cfg(feature = "rp235x")]
+        {
+            cfg.status_n = StatusN::This(1);
+        }
+        cfg.use_program(&program.prg, &[]);
+        let mut exec = cfg.get_exec();
+        exec.jmp_pin = probe_pin;
+        // SAFETY: only the jump pin is changed, it does not depend on the program.
+        unsafe { cfg.set_exec(exec) };
+        sm.set_config(&cfg);
+        init_phase(&mut sm);
+
+        sm.set_enable(true);
+        Self {
+            sm,
+            probe,
+            timing: sys_timing().with_loop_duration(PROBE_LOOP_DURATION),
+        }
+    }
+
+    /// Wait for the probe to become active and return the encoder state at that moment.
+    ///
+    /// If the probe is already active this waits for it to be released first,
+    /// so every call reports a new edge.
+    pub async fn capture(&mut self) -> ProbeCapture {
+        while !self.discard_stale() {
+            // Inversion is applied at the pad, so high is always inactive.
+            self.probe.wait_for_high().await;
+        }
+        let rx = self.sm.rx();
+        let direction_duration = rx.wait_pull().await;
+        let step = rx.wait_pull().await;
+        self.decode(direction_duration, step)
+    }
+
+    /// Return the capture waiting in the FIFO, if any.
+    ///
+    /// The state machine captures whenever the probe is active and the FIFO is empty,
+    /// so only the first capture after the probe becomes active is timed at the edge.
+    /// While the probe is held every call returns a new capture, taken when the previous one
+    /// was read. Use [`Self::capture`] to only get edges.
+    pub fn try_capture(&mut self) -> Option<ProbeCapture> {
+        let rx = self.sm.rx();
+        if rx.level() < 2 {
+            return None;
+        }
+        let direction_duration = rx.try_pull()?;
+        let step = rx.try_pull()?;
+        Some(self.decode(direction_duration, step))
+    }
+
+    /// Drop captures taken before now, this re-arms the state machine.
+    ///
+    /// Returns false, dropping nothing, if the probe is active. A capture in the FIFO may then
+    /// belong to the current edge, and dropping one taken while the probe was held would
+    /// only make the state machine capture again.
+    fn discard_stale(&mut self) -> bool {
+        let Self { sm, probe, .. } = self;
+        let rx = sm.rx();
+        critical_section::with(|_| {
+            // Read the level before the pin, anything already in the FIFO was captured
+            // while the probe was active before that.
+            // Round up in case the state machine is half way through pushing a capture.
+            let stale = rx.level().div_ceil(2);
+            if probe.is_low() {
+                return false;
+            }
+            for _ in 0..stale {
+                block_on(rx.wait_pull());
+                block_on(rx.wait_pull());
+            }
+            true
+        })
+    }
+
+    fn decode(&mut self, direction_duration: u32, step: u32) -> ProbeCapture {
+        // Pick up system clock changes made since the last capture.
+        self.timing = self.timing.with_clock_hz(clk_sys_freq());
+        ProbeCapture::from_raw_with_timing(
+            direction_duration.cast_signed(),
+            step.cast_signed(),
+            &self.timing,
+        )
+    }
+}
@@ -0,0 +1,39 @@
+use embassy_rp::{
+    gpio::Pull,
+    pio::{Instance, Pin},
+};
+use embassy_time::{Duration, Timer};
+use pio_speed_encoder_logic::{PullTest, Step, UpdateError};
+
+/// How long a pull takes to move a floating input, including some cable capacitance.
+const PULL_SETTLE: Duration = Duration::from_micros(100);
+
+/// Pull each input down in turn and record the step `read` sees, see [`PullTest`].
+///
+/// The pull ups are always restored, even if a read fails.
+pub(crate) async fn pull_test<T: Instance>(
+    pin_a: &mut Pin<'_, T>,
+    pin_b: &mut Pin<'_, T>,
+    mut read: impl FnMut() -> Result<Step, UpdateError>,
+) -> Result<PullTest, UpdateError> {
+    let before = read()?;
+    let a_pulled_down = pulled_down(pin_a, &mut read).await?;
+    let b_pulled_down = pulled_down(pin_b, &mut read).await?;
+    Ok(PullTest {
+        before,
+        a_pulled_down,
+        b_pulled_down,
+    })
+}
+
+async fn pulled_down<T: Instance>(
+    pin: &mut Pin<'_, T>,
+    read: &mut impl FnMut() -> Result<Step, UpdateError>,
+) -> Result<Step, UpdateError> {
+    pin.set_pull(Pull::Down);
+    Timer::after(PULL_SETTLE).await;
+    let step = read();
+    pin.set_pull(Pull::Up);
+    Timer::after(PULL_SETTLE).await;
+    step
+}